@@ -0,0 +1,378 @@
+//! Scripted in-process kernel for tests.
+//!
+//! Replies are matched by substring against the evaluated code, first rule
+//! wins. Code that matches no rule is echoed back as its own output, which is
+//! enough for most lifecycle tests.
+
+use std::sync::{
+  Arc,
+  Mutex
+};
+use std::thread;
+use std::time::{
+  Duration,
+  Instant
+};
+
+use anyhow::anyhow;
+
+use super::{
+  Kernel,
+  KernelBackend
+};
+use crate::expr_tree::ExprTree;
+use crate::wolfram::{
+  self,
+  AbortSignal,
+  EvalEvent,
+  EvalOptions,
+  EvalResult,
+  FormattedOutput,
+  GraphicsArtifact,
+  GraphicsFormat,
+  InputKind,
+  InputRequest,
+  InvalidSyntax,
+  LinkDead,
+  OutputFormat,
+  Part,
+  WolframMessage
+};
+
+/// How often a delayed reply checks for an abort request.
+const ABORT_POLL: Duration = Duration::from_millis(5);
+
+#[derive(Clone, Debug)]
+pub enum MockReply {
+  Output(EvalResult),
+  Failure(String),
+  Delay(Duration, Box<MockReply>),
+  /// Report events, as `MathematicaMCP`Progress` calls would, then reply.
+  Report(Vec<EvalEvent>, Box<MockReply>),
+  /// Ask for input like `Input[]` would and return the answer as the output.
+  Input(InputRequest),
+  /// Kill the kernel mid-evaluation, as a crash or a lost link would.
+  Crash,
+  /// Fail to parse, as the kernel does when the code has a syntax error.
+  InvalidSyntax
+}
+
+impl MockReply {
+  pub fn output(output: &str) -> Self {
+    Self::Output(EvalResult {
+      output:     output.to_string(),
+      outputs:    Vec::new(),
+      logs:       Vec::new(),
+      graphics:   Vec::new(),
+      messages:   Vec::new(),
+      aborted:    false,
+      expression: None
+    })
+  }
+
+  pub fn failure(message: &str) -> Self {
+    Self::Failure(message.to_string())
+  }
+
+  pub fn crash() -> Self {
+    Self::Crash
+  }
+
+  pub fn invalid_syntax() -> Self {
+    Self::InvalidSyntax
+  }
+
+  pub fn input(
+    kind: InputKind,
+    prompt: &str
+  ) -> Self {
+    Self::Input(InputRequest {
+      kind,
+      prompt: prompt.to_string()
+    })
+  }
+
+  pub fn with_logs(
+    mut self,
+    logs: &[&str]
+  ) -> Self {
+    if let Self::Output(res) = &mut self {
+      res.logs = logs.iter().map(|l| l.to_string()).collect();
+    }
+    self
+  }
+
+  /// Report a rendered graphic with `head` at `position` in the result. It is
+  /// tagged with whatever format the evaluation asks for.
+  pub fn with_graphics(
+    mut self,
+    position: &[Part],
+    head: &str,
+    data: &str
+  ) -> Self {
+    if let Self::Output(res) = &mut self {
+      res.graphics.push(GraphicsArtifact {
+        position: position.to_vec(),
+        head:     head.to_string(),
+        format:   GraphicsFormat::default(),
+        data:     data.to_string()
+      });
+    }
+    self
+  }
+
+  pub fn with_message(
+    mut self,
+    symbol: &str,
+    tag: &str,
+    text: &str,
+    arguments: &[&str]
+  ) -> Self {
+    if let Self::Output(res) = &mut self {
+      res.messages.push(WolframMessage {
+        symbol:    symbol.to_string(),
+        tag:       tag.to_string(),
+        text:      text.to_string(),
+        arguments: arguments.iter().map(|a| a.to_string()).collect()
+      });
+    }
+    self
+  }
+
+  /// Render the result as `text` when `format` is requested. Formats without
+  /// an explicit rendering fall back to the plain output.
+  pub fn with_rendering(
+    mut self,
+    format: OutputFormat,
+    text: &str
+  ) -> Self {
+    if let Self::Output(res) = &mut self {
+      res.outputs.push(FormattedOutput {
+        format,
+        text: text.to_string()
+      });
+    }
+    self
+  }
+
+  /// Return `tree` when the caller asks for the expression tree.
+  pub fn with_expression(
+    mut self,
+    tree: ExprTree
+  ) -> Self {
+    if let Self::Output(res) = &mut self {
+      res.expression = Some(tree);
+    }
+    self
+  }
+
+  /// Block the worker thread for `delay` before replying, like a long
+  /// computation would. An abort request cuts the delay short.
+  pub fn delayed(
+    self,
+    delay: Duration
+  ) -> Self {
+    Self::Delay(delay, Box::new(self))
+  }
+
+  /// Report `events` to the evaluation's event channel before replying. Logs
+  /// are reported as `Print` events on their own.
+  pub fn reporting(
+    self,
+    events: &[EvalEvent]
+  ) -> Self {
+    Self::Report(events.to_vec(), Box::new(self))
+  }
+}
+
+#[derive(Default)]
+struct MockState {
+  rules:          Vec<(String, MockReply)>,
+  launch_failure: Option<String>,
+  launches:       usize,
+  /// Kernels launched before this many launches are dead.
+  killed_before:  usize,
+  evaluations:    Vec<String>
+}
+
+#[derive(Clone, Default)]
+pub struct MockBackend {
+  state: Arc<Mutex<MockState>>
+}
+
+impl MockBackend {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Reply with `reply` whenever the evaluated code contains `fragment`.
+  pub fn on(
+    self,
+    fragment: &str,
+    reply: MockReply
+  ) -> Self {
+    self.state.lock().unwrap().rules.push((fragment.to_string(), reply));
+    self
+  }
+
+  /// Make every subsequent launch fail with `message`.
+  pub fn fail_launch(
+    self,
+    message: &str
+  ) -> Self {
+    self.state.lock().unwrap().launch_failure = Some(message.to_string());
+    self
+  }
+
+  /// Let subsequent launches succeed again.
+  pub fn allow_launch(&self) {
+    self.state.lock().unwrap().launch_failure = None;
+  }
+
+  /// Kill every kernel launched so far, as if their processes had exited.
+  pub fn kill_kernels(&self) {
+    let mut state = self.state.lock().unwrap();
+    state.killed_before = state.launches;
+  }
+
+  pub fn launches(&self) -> usize {
+    self.state.lock().unwrap().launches
+  }
+
+  /// Every piece of code evaluated so far, across all kernels.
+  pub fn evaluations(&self) -> Vec<String> {
+    self.state.lock().unwrap().evaluations.clone()
+  }
+}
+
+impl KernelBackend for MockBackend {
+  type Kernel = MockKernel;
+
+  fn launch(&self) -> anyhow::Result<Self::Kernel> {
+    let mut state = self.state.lock().unwrap();
+    if let Some(msg) = &state.launch_failure {
+      return Err(wolfram::launch_error(msg.clone()));
+    }
+    state.launches += 1;
+    Ok(MockKernel {
+      backend: self.clone(),
+      index:   state.launches - 1,
+      crashed: false
+    })
+  }
+}
+
+pub struct MockKernel {
+  backend: MockBackend,
+  /// Which launch this kernel came from.
+  index:   usize,
+  crashed: bool
+}
+
+impl Kernel for MockKernel {
+  fn evaluate(
+    &mut self,
+    code: &str,
+    options: &EvalOptions,
+    abort: &AbortSignal
+  ) -> anyhow::Result<EvalResult> {
+    let reply = {
+      let mut state = self.backend.state.lock().unwrap();
+      state.evaluations.push(code.to_string());
+      state
+        .rules
+        .iter()
+        .find(|(fragment, _)| code.contains(fragment.as_str()))
+        .map(|(_, reply)| reply.clone())
+        .unwrap_or_else(|| MockReply::output(code))
+    };
+    let res = play(reply, options, abort);
+    if res.as_ref().is_err_and(|e| e.is::<LinkDead>()) {
+      self.crashed = true;
+    }
+    res
+  }
+
+  fn is_alive(&mut self) -> bool {
+    !self.crashed && self.index >= self.backend.state.lock().unwrap().killed_before
+  }
+}
+
+fn play(
+  reply: MockReply,
+  options: &EvalOptions,
+  abort: &AbortSignal
+) -> anyhow::Result<EvalResult> {
+  match reply {
+    | MockReply::Output(mut res) => {
+      options.graphics.export_args()?;
+      let format = options.graphics.format.unwrap_or_default();
+      for graphic in &mut res.graphics {
+        graphic.format = format;
+      }
+      let outputs: Vec<FormattedOutput> = options
+        .output_formats
+        .iter()
+        .map(|&format| {
+          let text = res
+            .outputs
+            .iter()
+            .find(|o| o.format == format)
+            .map_or_else(|| res.output.clone(), |o| o.text.clone());
+          FormattedOutput {
+            format,
+            text
+          }
+        })
+        .collect();
+      if let Some(first) = outputs.first() {
+        res.output = first.text.clone();
+      }
+      res.outputs = outputs;
+      if !options.include_expression {
+        res.expression = None;
+      }
+      if let Some(events) = &options.events {
+        for log in &res.logs {
+          events.send(EvalEvent::Print(log.clone()));
+        }
+      }
+      Ok(res)
+    }
+    | MockReply::Failure(msg) => Err(anyhow!(msg)),
+    | MockReply::Delay(delay, reply) => {
+      let deadline = Instant::now() + delay;
+      while Instant::now() < deadline {
+        if abort.is_requested() {
+          return Ok(EvalResult::aborted(Vec::new()));
+        }
+        thread::sleep(ABORT_POLL);
+      }
+      play(*reply, options, abort)
+    }
+    | MockReply::Report(reported, reply) => {
+      if let Some(events) = &options.events {
+        for event in reported {
+          events.send(event);
+        }
+      }
+      play(*reply, options, abort)
+    }
+    | MockReply::Input(request) => {
+      match request.ask(options.input.as_ref(), abort) {
+        | Ok(answer) => play(MockReply::output(&answer), options, abort),
+        | Err(_) if abort.is_requested() => Ok(EvalResult::aborted(Vec::new())),
+        | Err(e) => Err(e)
+      }
+    }
+    | MockReply::Crash => {
+      Err(
+        LinkDead {
+          detail: "raw_next_packet failed: WSTP link died".to_string()
+        }
+        .into()
+      )
+    }
+    | MockReply::InvalidSyntax => Err(InvalidSyntax.into())
+  }
+}
@@ -0,0 +1,45 @@
+//! Kernel backends.
+//!
+//! `SessionManager` never talks to WSTP directly; it launches kernels and
+//! evaluates code through a [`KernelBackend`]. The real implementation lives
+//! in [`wstp`], and a scripted in-process [`mock`] backend is available to
+//! tests so that everything above `wolfram.rs` can run without a Wolfram
+//! license.
+
+#[cfg(test)]
+pub mod mock;
+mod wstp;
+
+pub use self::wstp::WstpBackend;
+use crate::wolfram::{
+  AbortSignal,
+  EvalOptions,
+  EvalResult
+};
+
+/// Launches kernels for new sessions.
+///
+/// A backend is cloned into every session worker thread, and `launch` runs on
+/// that thread. The returned [`Kernel`] never leaves it, so it does not need to
+/// be `Send`.
+pub trait KernelBackend: Clone + Send + Sync + 'static {
+  type Kernel: Kernel;
+
+  fn launch(&self) -> anyhow::Result<Self::Kernel>;
+}
+
+/// A running kernel owned by a single session worker thread.
+pub trait Kernel {
+  /// Evaluate `code`. When `abort` is raised mid-evaluation the kernel should
+  /// stop, return [`EvalResult::aborted`], and stay usable. When the kernel
+  /// dies the error should be a [`LinkDead`](crate::wolfram::LinkDead).
+  fn evaluate(
+    &mut self,
+    code: &str,
+    options: &EvalOptions,
+    abort: &AbortSignal
+  ) -> anyhow::Result<EvalResult>;
+
+  /// Whether the kernel is still running, checked before each evaluation.
+  fn is_alive(&mut self) -> bool;
+}
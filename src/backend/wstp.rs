@@ -0,0 +1,50 @@
+use super::{
+  Kernel,
+  KernelBackend
+};
+use crate::config::KernelConfig;
+use crate::wolfram::{
+  self,
+  AbortSignal,
+  EvalOptions,
+  EvalResult,
+  KernelProcess
+};
+
+/// Launches real Wolfram kernels over WSTP.
+#[derive(Clone, Debug, Default)]
+pub struct WstpBackend {
+  kernel: KernelConfig
+}
+
+impl WstpBackend {
+  pub fn new(kernel: KernelConfig) -> Self {
+    Self {
+      kernel
+    }
+  }
+}
+
+impl KernelBackend for WstpBackend {
+  type Kernel = KernelProcess;
+
+  fn launch(&self) -> anyhow::Result<Self::Kernel> {
+    let kernel_cmd = wolfram::resolve_kernel_cmd(self.kernel.path.as_deref())?;
+    wolfram::launch_link(&kernel_cmd, &self.kernel.args)
+  }
+}
+
+impl Kernel for KernelProcess {
+  fn evaluate(
+    &mut self,
+    code: &str,
+    options: &EvalOptions,
+    abort: &AbortSignal
+  ) -> anyhow::Result<EvalResult> {
+    wolfram::evaluate(self.link(), code, options, abort)
+  }
+
+  fn is_alive(&mut self) -> bool {
+    KernelProcess::is_alive(self)
+  }
+}
@@ -0,0 +1,446 @@
+//! Layered configuration.
+//!
+//! Every setting has a built-in default that can be overridden, in increasing
+//! order of precedence, by a TOML file, an environment variable and a
+//! command-line flag. The layers are merged as TOML tables before the result is
+//! deserialized, so all three accept the same values, and each setting
+//! remembers which layer it came from for `config show`.
+//!
+//! The file is `--config <PATH>`, else `$MATHEMATICA_MCP_CONFIG`, else
+//! `mathematica-mcp.toml` in the working directory if there is one. The
+//! environment variable for `section.key` is `MATHEMATICA_MCP_SECTION_KEY`;
+//! `WOLFRAM_KERNEL_PATH` is honored for `kernel.path` as well.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::{
+  Path,
+  PathBuf
+};
+use std::time::Duration;
+use std::{
+  env,
+  fmt,
+  fs
+};
+
+use anyhow::{
+  Context,
+  anyhow
+};
+use serde::{
+  Deserialize,
+  Deserializer,
+  Serialize
+};
+
+use crate::pool::PoolConfig;
+use crate::session::SessionOptions;
+use crate::wolfram::GraphicsOptions;
+
+pub const CONFIG_ENV: &str = "MATHEMATICA_MCP_CONFIG";
+const DEFAULT_FILE: &str = "mathematica-mcp.toml";
+const ENV_PREFIX: &str = "MATHEMATICA_MCP_";
+
+/// Every setting, as `section.key`.
+pub const KEYS: &[&str] = &[
+  "session.idle_timeout_secs",
+  "session.cleanup_interval_secs",
+  "session.eval_timeout_secs",
+  "session.max_sessions",
+  "session.queue_timeout_secs",
+  "session.auto_restart",
+  "session.job_retention_secs",
+  "session.history_limit",
+  "pool.min_idle",
+  "pool.max_kernels",
+  "kernel.path",
+  "kernel.args",
+  "graphics.format",
+  "graphics.resolution",
+  "graphics.image_size",
+  "graphics.background",
+  "repl.history_path"
+];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+  pub session:  SessionConfig,
+  pub pool:     PoolConfig,
+  pub kernel:   KernelConfig,
+  /// Graphics export defaults for every session.
+  pub graphics: GraphicsOptions,
+  pub repl:     ReplConfig
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+  /// Close sessions idle for longer than this.
+  pub idle_timeout_secs:     u64,
+  /// How often to look for idle sessions.
+  pub cleanup_interval_secs: u64,
+  /// Eval timeout for calls that do not set their own.
+  pub eval_timeout_secs:     u64,
+  /// Most sessions open at once; unset means no limit.
+  pub max_sessions:          Option<usize>,
+  /// How long a create request over `max_sessions` waits for a session to
+  /// close; 0 rejects it at once.
+  pub queue_timeout_secs:    u64,
+  /// Relaunch a session's kernel when it dies, replaying its setup code.
+  pub auto_restart:          bool,
+  /// How long a finished job's result is kept for `mathematica_job_result`.
+  pub job_retention_secs:    u64,
+  /// Most evaluations a session's history keeps; older ones are dropped.
+  pub history_limit:         usize
+}
+
+impl Default for SessionConfig {
+  fn default() -> Self {
+    Self {
+      idle_timeout_secs:     1800,
+      cleanup_interval_secs: 60,
+      eval_timeout_secs:     60,
+      max_sessions:          None,
+      queue_timeout_secs:    0,
+      auto_restart:          true,
+      job_retention_secs:    3600,
+      history_limit:         1000
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KernelConfig {
+  /// Kernel executable; discovered when unset.
+  pub path: Option<String>,
+  /// Extra kernel command-line arguments, as a list or a whitespace-separated
+  /// string.
+  #[serde(deserialize_with = "words")]
+  pub args: Vec<String>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplConfig {
+  pub history_path: PathBuf
+}
+
+impl Default for ReplConfig {
+  fn default() -> Self {
+    Self {
+      history_path: PathBuf::from(".cache/mathematica_repl_history.txt")
+    }
+  }
+}
+
+impl Config {
+  pub fn session_options(&self) -> SessionOptions {
+    SessionOptions {
+      pool:             self.pool.clone(),
+      idle_timeout:     Duration::from_secs(self.session.idle_timeout_secs),
+      cleanup_interval: Duration::from_secs(self.session.cleanup_interval_secs),
+      eval_timeout:     Duration::from_secs(self.session.eval_timeout_secs),
+      max_sessions:     self.session.max_sessions,
+      queue_timeout:    Duration::from_secs(self.session.queue_timeout_secs),
+      auto_restart:     self.session.auto_restart,
+      job_retention:    Duration::from_secs(self.session.job_retention_secs),
+      history_limit:    self.session.history_limit,
+      graphics:         self.graphics.clone(),
+      kernel:           self.kernel.clone()
+    }
+  }
+}
+
+/// The layer a setting's effective value came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+  Default,
+  File(PathBuf),
+  Env(String),
+  Cli
+}
+
+impl fmt::Display for Source {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>
+  ) -> fmt::Result {
+    match self {
+      | Self::Default => write!(f, "default"),
+      | Self::File(path) => write!(f, "file {}", path.display()),
+      | Self::Env(var) => write!(f, "env {var}"),
+      | Self::Cli => write!(f, "command line")
+    }
+  }
+}
+
+/// The effective configuration and where each setting came from.
+#[derive(Debug)]
+pub struct LoadedConfig {
+  pub config: Config,
+  pub file:   Option<PathBuf>,
+  sources:    BTreeMap<String, Source>
+}
+
+impl LoadedConfig {
+  /// Load from the config file, the process environment and `cli`, which maps
+  /// settings to the values of the flags that were given.
+  pub fn load(
+    file: Option<&Path>,
+    cli: &[(&str, toml::Value)]
+  ) -> anyhow::Result<Self> {
+    Self::load_with(file, |var| env::var(var).ok(), cli)
+  }
+
+  fn load_with(
+    file: Option<&Path>,
+    env: impl Fn(&str) -> Option<String>,
+    cli: &[(&str, toml::Value)]
+  ) -> anyhow::Result<Self> {
+    let mut table = toml::Table::new();
+    let mut sources = BTreeMap::new();
+
+    let file = match file {
+      | Some(path) => Some(path.to_path_buf()),
+      | None => {
+        env(CONFIG_ENV)
+          .filter(|p| !p.is_empty())
+          .map(PathBuf::from)
+          .or_else(|| Path::new(DEFAULT_FILE).exists().then(|| PathBuf::from(DEFAULT_FILE)))
+      }
+    };
+    if let Some(path) = &file {
+      let text = fs::read_to_string(path)
+        .with_context(|| format!("failed to read config file {}", path.display()))?;
+      let parsed: toml::Table =
+        text.parse().with_context(|| format!("invalid TOML in {}", path.display()))?;
+      for (section, values) in parsed {
+        let toml::Value::Table(values) = values else {
+          return Err(anyhow!("`{section}` in {} must be a [section]", path.display()));
+        };
+        for (key, value) in values {
+          let key = format!("{section}.{key}");
+          if !KEYS.contains(&key.as_str()) {
+            return Err(anyhow!("unknown setting `{key}` in {}", path.display()));
+          }
+          set(&mut table, &key, value);
+          sources.insert(key, Source::File(path.clone()));
+        }
+      }
+    }
+
+    for key in KEYS {
+      let mut vars = vec![env_var(key)];
+      if *key == "kernel.path" {
+        vars.push("WOLFRAM_KERNEL_PATH".to_string());
+      }
+      let Some((var, raw)) =
+        vars.into_iter().find_map(|var| env(&var).filter(|v| !v.is_empty()).map(|v| (var, v)))
+      else {
+        continue;
+      };
+      set(&mut table, key, env_value(&raw));
+      sources.insert(key.to_string(), Source::Env(var));
+    }
+
+    for (key, value) in cli {
+      set(&mut table, key, value.clone());
+      sources.insert(key.to_string(), Source::Cli);
+    }
+
+    let mut config: Config =
+      toml::Value::Table(table).try_into().context("invalid configuration")?;
+    config.graphics.export_args().context("invalid graphics settings")?;
+    if config.session.cleanup_interval_secs == 0 {
+      return Err(anyhow!("`session.cleanup_interval_secs` must be at least 1"));
+    }
+    if config.session.history_limit == 0 {
+      return Err(anyhow!("`session.history_limit` must be at least 1"));
+    }
+    // Pooled kernels hold licenses too, so they stay within the session cap.
+    if let Some(max_sessions) = config.session.max_sessions {
+      config.pool.max_kernels = config.pool.max_kernels.min(max_sessions);
+    }
+    Ok(Self {
+      config,
+      file,
+      sources
+    })
+  }
+
+  pub fn source(
+    &self,
+    key: &str
+  ) -> &Source {
+    self.sources.get(key).unwrap_or(&Source::Default)
+  }
+
+  /// Every setting with its effective value and source, for `config show`.
+  pub fn show(&self) -> anyhow::Result<String> {
+    let values = toml::Table::try_from(&self.config)?;
+    let mut out = String::new();
+    match &self.file {
+      | Some(path) => writeln!(out, "# config file: {}", path.display())?,
+      | None => writeln!(out, "# config file: none")?
+    }
+    for key in KEYS {
+      let (section, name) = key.split_once('.').expect("keys are section.key");
+      let setting = match values.get(section).and_then(|s| s.get(name)) {
+        | Some(value) => format!("{key} = {value}"),
+        | None => format!("# {key} unset")
+      };
+      writeln!(out, "{setting:<48} # {}", self.source(key))?;
+    }
+    Ok(out)
+  }
+}
+
+/// `MATHEMATICA_MCP_SECTION_KEY` for `section.key`.
+fn env_var(key: &str) -> String {
+  format!("{ENV_PREFIX}{}", key.replace('.', "_").to_uppercase())
+}
+
+/// Read an environment variable as a TOML value (`60`, `[400, 300]`, ...),
+/// falling back to a plain string so paths and color names need no quotes.
+fn env_value(raw: &str) -> toml::Value {
+  format!("v = {raw}")
+    .parse::<toml::Table>()
+    .ok()
+    .and_then(|mut t| t.remove("v"))
+    .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+fn set(
+  table: &mut toml::Table,
+  key: &str,
+  value: toml::Value
+) {
+  let (section, name) = key.split_once('.').expect("keys are section.key");
+  let section = table
+    .entry(section)
+    .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+    .as_table_mut()
+    .expect("sections are tables");
+  section.insert(name.to_string(), value);
+}
+
+fn words<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum Words {
+    One(String),
+    Many(Vec<String>)
+  }
+  Ok(match Words::deserialize(deserializer)? {
+    | Words::One(s) => s.split_whitespace().map(String::from).collect(),
+    | Words::Many(words) => words
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::*;
+  use crate::wolfram::{
+    GraphicsFormat,
+    ImageSize
+  };
+
+  fn load(
+    file: &str,
+    env: &[(&str, &str)],
+    cli: &[(&str, toml::Value)]
+  ) -> anyhow::Result<LoadedConfig> {
+    let dir = env::temp_dir().join(format!("mathematica-mcp-config-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, file).unwrap();
+    let env: HashMap<String, String> =
+      env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    let loaded = LoadedConfig::load_with(Some(&path), |var| env.get(var).cloned(), cli);
+    fs::remove_dir_all(&dir).unwrap();
+    loaded
+  }
+
+  #[test]
+  fn cli_beats_env_beats_file_beats_default() {
+    let loaded = load(
+      "[session]\nidle_timeout_secs = 600\neval_timeout_secs = 30\nmax_sessions = \
+       2\n\n[graphics]\nformat = \"SVG\"\n",
+      &[
+        ("MATHEMATICA_MCP_SESSION_EVAL_TIMEOUT_SECS", "90"),
+        ("MATHEMATICA_MCP_SESSION_MAX_SESSIONS", "3")
+      ],
+      &[("session.max_sessions", toml::Value::Integer(4))]
+    )
+    .unwrap();
+    let config = &loaded.config;
+    assert_eq!(config.session.idle_timeout_secs, 600);
+    assert_eq!(config.session.eval_timeout_secs, 90);
+    assert_eq!(config.session.max_sessions, Some(4));
+    assert_eq!(config.session.cleanup_interval_secs, 60);
+    assert_eq!(config.graphics.format, Some(GraphicsFormat::Svg));
+
+    assert!(matches!(loaded.source("session.idle_timeout_secs"), Source::File(_)));
+    assert_eq!(
+      loaded.source("session.eval_timeout_secs"),
+      &Source::Env("MATHEMATICA_MCP_SESSION_EVAL_TIMEOUT_SECS".to_string())
+    );
+    assert_eq!(loaded.source("session.max_sessions"), &Source::Cli);
+    assert_eq!(loaded.source("session.cleanup_interval_secs"), &Source::Default);
+
+    let shown = loaded.show().unwrap();
+    assert!(shown.contains("session.max_sessions = 4"), "{shown}");
+    assert!(shown.contains("# graphics.background unset"), "{shown}");
+  }
+
+  #[test]
+  fn env_values_parse_as_toml_or_plain_strings() {
+    let loaded = load(
+      "",
+      &[
+        ("WOLFRAM_KERNEL_PATH", "/opt/Wolfram/WolframKernel"),
+        ("MATHEMATICA_MCP_KERNEL_ARGS", "-noinit -nopaclet"),
+        ("MATHEMATICA_MCP_GRAPHICS_IMAGE_SIZE", "[400, 300]"),
+        ("MATHEMATICA_MCP_GRAPHICS_BACKGROUND", "White")
+      ],
+      &[]
+    )
+    .unwrap();
+    let config = &loaded.config;
+    assert_eq!(config.kernel.path.as_deref(), Some("/opt/Wolfram/WolframKernel"));
+    assert_eq!(config.kernel.args, vec!["-noinit", "-nopaclet"]);
+    assert_eq!(config.graphics.image_size, Some(ImageSize::WidthHeight([400, 300])));
+    assert_eq!(config.graphics.background.as_deref(), Some("White"));
+    assert_eq!(loaded.source("kernel.path"), &Source::Env("WOLFRAM_KERNEL_PATH".to_string()));
+  }
+
+  #[test]
+  fn pool_never_outgrows_the_session_cap() {
+    let loaded =
+      load("[session]\nmax_sessions = 2\n\n[pool]\nmax_kernels = 6\n", &[], &[]).unwrap();
+    assert_eq!(loaded.config.pool.max_kernels, 2);
+
+    let loaded = load("[session]\nmax_sessions = 8\n", &[], &[]).unwrap();
+    assert_eq!(loaded.config.pool.max_kernels, 4);
+  }
+
+  #[test]
+  fn rejects_unknown_and_invalid_settings() {
+    let err = load("[session]\nidle_timeout = 5\n", &[], &[]).unwrap_err();
+    assert!(err.to_string().contains("unknown setting `session.idle_timeout`"), "{err}");
+
+    assert!(load("[pool]\nmin_idle = \"many\"\n", &[], &[]).is_err());
+    assert!(load("[graphics]\nbackground = \"#12\"\n", &[], &[]).is_err());
+
+    let err = load("", &[("MATHEMATICA_MCP_SESSION_CLEANUP_INTERVAL_SECS", "0")], &[]).unwrap_err();
+    assert!(err.to_string().contains("session.cleanup_interval_secs"), "{err}");
+
+    let err = load("[session]\nhistory_limit = 0\n", &[], &[]).unwrap_err();
+    assert!(err.to_string().contains("session.history_limit"), "{err}");
+  }
+}
@@ -0,0 +1,147 @@
+//! Jupyter notebook (`.ipynb`) export.
+//!
+//! Cells are written as nbformat 4 code cells in the Wolfram Language kernel's
+//! language, so JupyterLab and GitHub render the outputs and the notebook can
+//! be re-run with the Wolfram Language for Jupyter kernel.
+
+use serde_json::{
+  Value,
+  json
+};
+
+use super::{
+  CellOutput,
+  Transcript,
+  decode
+};
+use crate::wolfram::GraphicsFormat;
+
+pub(super) fn render(transcript: &Transcript) -> anyhow::Result<String> {
+  let mut cells = Vec::new();
+  for cell in &transcript.cells {
+    let mut outputs = Vec::new();
+    for output in &cell.outputs {
+      outputs.push(match output {
+        | CellOutput::Print(text) => stream("stdout", text),
+        | CellOutput::Message(text) => stream("stderr", text),
+        | CellOutput::Result(text) => {
+          json!({
+            "output_type": "execute_result",
+            "execution_count": cell.n,
+            "data": {"text/plain": lines(text)},
+            "metadata": {}
+          })
+        }
+        | CellOutput::Graphic {
+          format,
+          data
+        } => {
+          // Binary formats stay base64; SVG is stored as its XML text.
+          let encoded = match format {
+            | GraphicsFormat::Svg => json!(lines(&String::from_utf8_lossy(&decode(data)?))),
+            | _ => json!(data)
+          };
+          json!({
+            "output_type": "display_data",
+            "data": {format.mime_type(): encoded, "text/plain": [format!("-{}-", format.export_name())]},
+            "metadata": {}
+          })
+        }
+        | CellOutput::Error(text) => {
+          json!({
+            "output_type": "error",
+            "ename": "EvaluationError",
+            "evalue": text,
+            "traceback": [text]
+          })
+        }
+      });
+    }
+    cells.push(json!({
+      "cell_type": "code",
+      "execution_count": cell.n,
+      "metadata": {},
+      "source": lines(&cell.input),
+      "outputs": outputs
+    }));
+  }
+  let notebook = json!({
+    "nbformat": 4,
+    "nbformat_minor": 4,
+    "metadata": {
+      "kernelspec": {
+        "display_name": "Wolfram Language",
+        "language": "Wolfram Language",
+        "name": "wolframlanguage"
+      },
+      "language_info": {
+        "name": "Wolfram Language",
+        "codemirror_mode": "mathematica",
+        "file_extension": ".m",
+        "mimetype": "application/vnd.wolfram.m",
+        "pygments_lexer": "mathematica"
+      },
+      "title": format!("Mathematica session {}", transcript.session_id)
+    },
+    "cells": cells
+  });
+  Ok(serde_json::to_string_pretty(&notebook)? + "\n")
+}
+
+fn stream(
+  name: &str,
+  text: &str
+) -> Value {
+  json!({
+    "output_type": "stream",
+    "name": name,
+    "text": lines(&format!("{text}\n"))
+  })
+}
+
+/// Multi-line text the way nbformat stores it: one string per line, each
+/// keeping its newline.
+fn lines(text: &str) -> Vec<&str> {
+  text.split_inclusive('\n').collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::export::TranscriptCell;
+
+  #[test]
+  fn cells_carry_outputs_in_jupyter_form() {
+    let transcript = Transcript {
+      session_id: "s1".to_string(),
+      cells:      vec![
+        TranscriptCell {
+          n:       1,
+          input:   "Print[\"hi\"];\nPlot[x, {x, 0, 1}]".to_string(),
+          outputs: vec![CellOutput::Print("hi".to_string()), CellOutput::Graphic {
+            format: GraphicsFormat::Png,
+            data:   "iVBOR".to_string()
+          }]
+        },
+        TranscriptCell {
+          n:       2,
+          input:   "1/0".to_string(),
+          outputs: vec![
+            CellOutput::Message("Power::infy: Infinite expression 1/0 encountered.".to_string()),
+            CellOutput::Result("ComplexInfinity".to_string()),
+          ]
+        },
+      ]
+    };
+    let notebook: Value = serde_json::from_str(&render(&transcript).unwrap()).unwrap();
+    assert_eq!(notebook["metadata"]["kernelspec"]["language"], "Wolfram Language");
+    let cells = notebook["cells"].as_array().unwrap();
+    assert_eq!(cells[0]["source"], json!(["Print[\"hi\"];\n", "Plot[x, {x, 0, 1}]"]));
+    assert_eq!(cells[0]["outputs"][0]["text"], json!(["hi\n"]));
+    assert_eq!(cells[0]["outputs"][1]["data"]["image/png"], "iVBOR");
+    assert_eq!(cells[1]["execution_count"], 2);
+    assert_eq!(cells[1]["outputs"][0]["name"], "stderr");
+    assert_eq!(cells[1]["outputs"][1]["output_type"], "execute_result");
+    assert_eq!(cells[1]["outputs"][1]["data"]["text/plain"], json!(["ComplexInfinity"]));
+  }
+}
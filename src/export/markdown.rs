@@ -0,0 +1,140 @@
+//! Markdown export.
+//!
+//! Inputs and results become fenced `wolfram` code blocks, `Print` output
+//! plain text blocks and messages block quotes. Graphics are written as image
+//! files in a `<name>_files` directory next to the document, the way
+//! nbconvert does it, so the result reads well on GitHub.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+
+use super::{
+  CellOutput,
+  Transcript,
+  decode
+};
+use crate::wolfram::GraphicsFormat;
+
+/// Write the document to `path`, its images beside it, or return it when there
+/// is no path, with the images inlined as data URIs.
+pub(super) fn export(
+  transcript: &Transcript,
+  path: Option<&Path>
+) -> anyhow::Result<Option<String>> {
+  let Some(path) = path else {
+    let document = render(transcript, |_, _, format, data| {
+      Ok(format!("data:{};base64,{data}", format.mime_type()))
+    })?;
+    return Ok(Some(document));
+  };
+  let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+  let files = format!("{stem}_files");
+  let dir = path.with_file_name(&files);
+  let document = render(transcript, |n, i, format, data| {
+    let name = format!("out-{n}-{i}.{}", format.extension());
+    fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let file = dir.join(&name);
+    fs::write(&file, decode(data)?)
+      .with_context(|| format!("failed to write {}", file.display()))?;
+    Ok(format!("{files}/{name}"))
+  })?;
+  fs::write(path, document).with_context(|| format!("failed to write {}", path.display()))?;
+  Ok(None)
+}
+
+/// The transcript as Markdown; `image(n, i, format, data)` stores the `i`th
+/// graphic of evaluation `n` and returns the link to it.
+fn render(
+  transcript: &Transcript,
+  mut image: impl FnMut(usize, usize, GraphicsFormat, &str) -> anyhow::Result<String>
+) -> anyhow::Result<String> {
+  let mut out = format!("# Mathematica session {}\n", transcript.session_id);
+  for cell in &transcript.cells {
+    let n = cell.n;
+    out += &format!("\n**In[{n}]:=**\n\n{}", fenced("wolfram", &cell.input));
+    let mut graphics = 0;
+    for output in &cell.outputs {
+      out += "\n";
+      match output {
+        | CellOutput::Print(text) => out += &fenced("text", text),
+        | CellOutput::Message(text) => out += &quoted(text),
+        | CellOutput::Result(text) => {
+          out += &format!("**Out[{n}]=**\n\n{}", fenced("wolfram", text));
+        }
+        | CellOutput::Graphic {
+          format,
+          data
+        } => {
+          graphics += 1;
+          out += &format!("![Out[{n}]]({})\n", image(n, graphics, *format, data)?);
+        }
+        | CellOutput::Error(text) => out += &quoted(&format!("**Error:** {text}"))
+      }
+    }
+  }
+  Ok(out)
+}
+
+/// A fenced code block, with a fence longer than any backtick run in `text`.
+fn fenced(
+  language: &str,
+  text: &str
+) -> String {
+  let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+  let fence = "`".repeat(longest.max(2) + 1);
+  format!("{fence}{language}\n{}\n{fence}\n", text.trim_end_matches('\n'))
+}
+
+fn quoted(text: &str) -> String {
+  text.lines().map(|line| format!("> {line}\n")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::export::TranscriptCell;
+
+  #[test]
+  fn graphics_are_written_beside_the_document() {
+    let transcript = Transcript {
+      session_id: "s1".to_string(),
+      cells:      vec![TranscriptCell {
+        n:       1,
+        input:   "Print[\"hi\"]; 1/0".to_string(),
+        outputs: vec![
+          CellOutput::Print("hi".to_string()),
+          CellOutput::Message("Power::infy: Infinite expression 1/0 encountered.".to_string()),
+          CellOutput::Result("ComplexInfinity".to_string()),
+          CellOutput::Graphic {
+            format: GraphicsFormat::Png,
+            // "PNG"
+            data:   "UE5H".to_string()
+          },
+        ]
+      }]
+    };
+    let dir = std::env::temp_dir().join(format!("md-export-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("work.md");
+    assert_eq!(export(&transcript, Some(&path)).unwrap(), None);
+    assert_eq!(
+      fs::read_to_string(&path).unwrap(),
+      "# Mathematica session s1\n\n**In[1]:=**\n\n```wolfram\nPrint[\"hi\"]; \
+       1/0\n```\n\n```text\nhi\n```\n\n> Power::infy: Infinite expression 1/0 \
+       encountered.\n\n**Out[1]=**\n\n```wolfram\nComplexInfinity\n```\n\n![Out[1]](work_files/\
+       out-1-1.png)\n"
+    );
+    assert_eq!(fs::read(dir.join("work_files/out-1-1.png")).unwrap(), b"PNG");
+    fs::remove_dir_all(&dir).unwrap();
+
+    let inline = export(&transcript, None).unwrap().unwrap();
+    assert!(inline.contains("![Out[1]](data:image/png;base64,UE5H)"), "{inline}");
+  }
+
+  #[test]
+  fn fences_outgrow_backticks_in_the_code() {
+    assert_eq!(fenced("wolfram", "s = \"````\""), "`````wolfram\ns = \"````\"\n`````\n");
+  }
+}
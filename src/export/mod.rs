@@ -0,0 +1,213 @@
+//! Session transcripts and the exporters that write them out.
+//!
+//! A [`Transcript`] is a session's history as a sequence of cells: the code
+//! that was evaluated and what it produced, in the order a notebook front end
+//! would show it. Every export format is rendered from it.
+
+mod ipynb;
+mod markdown;
+mod notebook;
+
+use std::path::{
+  Path,
+  PathBuf
+};
+
+use anyhow::{
+  Context,
+  anyhow
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use schemars::JsonSchema;
+use serde::{
+  Deserialize,
+  Serialize
+};
+
+use crate::backend::KernelBackend;
+use crate::history::HistoryEntry;
+use crate::session::SessionManager;
+use crate::wolfram::GraphicsFormat;
+
+/// A session's evaluations, ready to export.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transcript {
+  pub session_id: String,
+  pub cells:      Vec<TranscriptCell>
+}
+
+/// One evaluation: its input and everything it produced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscriptCell {
+  /// Position in the session's history, from 1, as in `In[n]`.
+  pub n:       usize,
+  pub input:   String,
+  pub outputs: Vec<CellOutput>
+}
+
+/// Something an evaluation produced, in display order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CellOutput {
+  /// `Print` output.
+  Print(String),
+  /// A kernel message, formatted as `sym::tag: text`.
+  Message(String),
+  /// The result in `InputForm`.
+  Result(String),
+  /// A graphics object or image in the result, base64-encoded.
+  Graphic {
+    format: GraphicsFormat,
+    data:   String
+  },
+  /// The evaluation failed before producing a result.
+  Error(String)
+}
+
+impl Transcript {
+  pub fn from_history(
+    session_id: &str,
+    entries: &[HistoryEntry]
+  ) -> Self {
+    Self {
+      session_id: session_id.to_string(),
+      cells:      entries.iter().map(TranscriptCell::from_entry).collect()
+    }
+  }
+}
+
+impl TranscriptCell {
+  fn from_entry(entry: &HistoryEntry) -> Self {
+    let mut outputs = Vec::new();
+    match &entry.result {
+      | Ok(res) => {
+        outputs.extend(res.logs.iter().cloned().map(CellOutput::Print));
+        outputs.extend(
+          res
+            .messages
+            .iter()
+            .map(|m| CellOutput::Message(format!("{}::{}: {}", m.symbol, m.tag, m.text)))
+        );
+        // When the result is itself a graphic its InputForm is just a
+        // placeholder; the image stands in for it.
+        if !res.graphics.iter().any(|g| g.position.is_empty()) {
+          outputs.push(CellOutput::Result(res.output.clone()));
+        }
+        outputs.extend(res.graphics.iter().map(|g| {
+          CellOutput::Graphic {
+            format: g.format,
+            data:   g.data.clone()
+          }
+        }));
+      }
+      | Err(e) => outputs.push(CellOutput::Error(e.clone()))
+    }
+    Self {
+      n: entry.n,
+      input: entry.code.clone(),
+      outputs
+    }
+  }
+}
+
+/// A file format a transcript can be exported to.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+  /// A Wolfram notebook (`.nb`), built by the session's kernel.
+  Notebook,
+  /// A Jupyter notebook (`.ipynb`) for the Wolfram Language kernel.
+  Ipynb,
+  /// Markdown with fenced code, images in files beside it.
+  Markdown
+}
+
+impl ExportFormat {
+  /// The format a file name implies, from its extension.
+  pub fn from_path(path: &Path) -> anyhow::Result<Self> {
+    match path.extension().and_then(|e| e.to_str()) {
+      | Some("nb") => Ok(Self::Notebook),
+      | Some("ipynb") => Ok(Self::Ipynb),
+      | Some("md" | "markdown") => Ok(Self::Markdown),
+      | _ => {
+        Err(anyhow!(
+          "cannot tell the export format from {}; use a .nb, .ipynb or .md file",
+          path.display()
+        ))
+      }
+    }
+  }
+
+  pub fn extension(self) -> &'static str {
+    match self {
+      | Self::Notebook => "nb",
+      | Self::Ipynb => "ipynb",
+      | Self::Markdown => "md"
+    }
+  }
+
+  pub fn mime_type(self) -> &'static str {
+    match self {
+      | Self::Notebook => "application/vnd.wolfram.nb",
+      | Self::Ipynb => "application/x-ipynb+json",
+      | Self::Markdown => "text/markdown"
+    }
+  }
+}
+
+/// What an export produced.
+#[derive(Debug)]
+pub struct Exported {
+  pub format:  ExportFormat,
+  /// Number of evaluations exported.
+  pub cells:   usize,
+  /// Where the file was written, or `None` when it was returned as `content`.
+  pub path:    Option<PathBuf>,
+  pub content: Option<String>
+}
+
+/// Export a session's history in `format`, to `path` when given and
+/// otherwise as a string.
+pub async fn export_session<B: KernelBackend>(
+  sessions: &SessionManager<B>,
+  session_id: &str,
+  format: ExportFormat,
+  path: Option<&Path>
+) -> anyhow::Result<Exported> {
+  let history = sessions.history(session_id).await?;
+  let transcript = Transcript::from_history(session_id, &history.entries());
+  let path = path.map(std::path::absolute).transpose()?;
+  let content = match format {
+    | ExportFormat::Notebook => {
+      notebook::export(sessions, session_id, &transcript, path.as_deref()).await?
+    }
+    | ExportFormat::Ipynb => write_or_return(path.as_deref(), ipynb::render(&transcript)?)?,
+    | ExportFormat::Markdown => markdown::export(&transcript, path.as_deref())?
+  };
+  Ok(Exported {
+    format,
+    cells: transcript.cells.len(),
+    path,
+    content
+  })
+}
+
+/// Write `content` to `path`, or hand it back when there is none.
+fn write_or_return(
+  path: Option<&Path>,
+  content: String
+) -> anyhow::Result<Option<String>> {
+  match path {
+    | Some(path) => {
+      std::fs::write(path, content)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+      Ok(None)
+    }
+    | None => Ok(Some(content))
+  }
+}
+
+/// The bytes of a base64-encoded graphic.
+fn decode(data: &str) -> anyhow::Result<Vec<u8>> {
+  STANDARD.decode(data).context("graphics data is not valid base64")
+}
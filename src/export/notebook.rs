@@ -0,0 +1,135 @@
+//! Wolfram notebook (`.nb`) export.
+//!
+//! The notebook is assembled as a `Notebook[...]` expression and written by
+//! the session's own kernel with `Export`, so the file is exactly what
+//! Mathematica would save: images are decoded into real boxes rather than
+//! pasted in as base64 blobs.
+
+use std::path::Path;
+
+use anyhow::{
+  anyhow,
+  bail
+};
+
+use super::{
+  CellOutput,
+  Transcript
+};
+use crate::backend::KernelBackend;
+use crate::session::SessionManager;
+use crate::wolfram::{
+  EvalOptions,
+  OutputFormat,
+  wl_string
+};
+
+/// Write the notebook to `path`, or return its contents when there is none.
+pub(super) async fn export<B: KernelBackend>(
+  sessions: &SessionManager<B>,
+  session_id: &str,
+  transcript: &Transcript,
+  path: Option<&Path>
+) -> anyhow::Result<Option<String>> {
+  let notebook = notebook_expr(transcript);
+  let code = match path {
+    | Some(path) => {
+      format!("Export[{}, {notebook}, \"NB\"]", quoted(&path.to_string_lossy()))
+    }
+    | None => format!("ExportString[{notebook}, \"NB\"]")
+  };
+  // OutputForm hands back a string result without quotes or escapes.
+  let options = EvalOptions {
+    output_formats: vec![OutputFormat::OutputForm],
+    ..EvalOptions::default()
+  };
+  let res = sessions.eval_unrecorded(session_id, &code, &options, sessions.eval_timeout()).await?;
+  if res.aborted || res.output == "$Failed" {
+    let messages: Vec<String> = res.messages.iter().map(|m| m.text.clone()).collect();
+    bail!("the kernel could not build the notebook: {}", messages.join("; "));
+  }
+  match path {
+    | Some(path) if res.output != path.to_string_lossy() => {
+      Err(anyhow!("the kernel could not write {}: {}", path.display(), res.output))
+    }
+    | Some(_) => Ok(None),
+    | None => Ok(Some(res.output))
+  }
+}
+
+/// Wolfram Language code for the transcript as a `Notebook` expression.
+pub(super) fn notebook_expr(transcript: &Transcript) -> String {
+  let mut cells = Vec::new();
+  for cell in &transcript.cells {
+    let n = cell.n;
+    cells.push(format!("Cell[{}, \"Input\", CellLabel -> \"In[{n}]:=\"]", quoted(&cell.input)));
+    for output in &cell.outputs {
+      cells.push(match output {
+        | CellOutput::Print(text) => format!("Cell[{}, \"Print\"]", quoted(text)),
+        | CellOutput::Message(text) | CellOutput::Error(text) => {
+          format!("Cell[{}, \"Message\", \"MSG\"]", quoted(text))
+        }
+        | CellOutput::Result(text) => {
+          format!("Cell[{}, \"Output\", CellLabel -> \"Out[{n}]=\"]", quoted(text))
+        }
+        // PDF imports as a list of pages; the first is the graphic.
+        | CellOutput::Graphic {
+          format,
+          data
+        } => {
+          format!(
+            "Cell[BoxData[ToBoxes[First[Flatten[{{ImportByteArray[BaseDecode[{}], {}]}}]]]], \
+             \"Output\", CellLabel -> \"Out[{n}]=\"]",
+            quoted(data),
+            quoted(format.export_name())
+          )
+        }
+      });
+    }
+  }
+  format!(
+    "Notebook[{{{}}}, WindowTitle -> {}]",
+    cells.join(", "),
+    quoted(&format!("Mathematica session {}", transcript.session_id))
+  )
+}
+
+/// `s` as a Wolfram Language string literal.
+fn quoted(s: &str) -> String {
+  format!("\"{}\"", wl_string(s))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::export::TranscriptCell;
+  use crate::wolfram::GraphicsFormat;
+
+  #[test]
+  fn cells_follow_the_transcript() {
+    let transcript = Transcript {
+      session_id: "s1".to_string(),
+      cells:      vec![TranscriptCell {
+        n:       1,
+        input:   "Print[\"hi\"]; Plot[x, {x, 0, 1}]".to_string(),
+        outputs: vec![
+          CellOutput::Print("hi".to_string()),
+          CellOutput::Message("Power::infy: Infinite expression 1/0 encountered.".to_string()),
+          CellOutput::Graphic {
+            format: GraphicsFormat::Png,
+            data:   "iVBORw0KGgo=".to_string()
+          },
+        ]
+      }]
+    };
+    assert_eq!(
+      notebook_expr(&transcript),
+      "Notebook[{Cell[\"Print[\\\"hi\\\"]; Plot[x, {x, 0, 1}]\", \"Input\", CellLabel -> \
+       \"In[1]:=\"], Cell[\"hi\", \"Print\"], Cell[\"Power::infy: Infinite expression 1/0 \
+       encountered.\", \"Message\", \"MSG\"], \
+       Cell[BoxData[ToBoxes[First[Flatten[{ImportByteArray[BaseDecode[\"iVBORw0KGgo=\"], \
+       \"PNG\"]}]]]], \"Output\", CellLabel -> \"Out[1]=\"]}, WindowTitle -> \"Mathematica \
+       session s1\"]"
+    );
+  }
+}
@@ -0,0 +1,266 @@
+//! Typed JSON tree of an evaluation result.
+//!
+//! WSTP only carries machine integers and reals, and the kernel sends symbols
+//! without their context when they are on `$ContextPath`, which
+//! `Link::get_expr` refuses to read. So the evaluation wrapper encodes the
+//! result on the kernel side first ([`ENCODER`]): everything that would not
+//! survive the link is rewritten into strings under heads in the
+//! `MathematicaMCP`` context, which is never on `$ContextPath` and therefore
+//! always arrives fully qualified. [`ExprTree::decode`] turns that back into a
+//! tree.
+
+use anyhow::anyhow;
+use wolfram_expr::{
+  Expr,
+  ExprKind
+};
+
+/// Context of every marker head the wrapper sends back.
+pub const CONTEXT: &str = "MathematicaMCP`";
+
+/// Kernel-side definition of `tree`, which turns any expression (held, so
+/// nothing evaluates again) into the encoding [`ExprTree::decode`] reads.
+/// `tree` must be a local symbol of the surrounding `Module`.
+pub const ENCODER: &str = r#"SetAttributes[tree, HoldAllComplete]; tree[e_] := Which[Developer`MachineIntegerQ[Unevaluated[e]], e, Head[Unevaluated[e]] === Integer, MathematicaMCP`Integer[ToString[e]], Head[Unevaluated[e]] === Rational, MathematicaMCP`Rational[ToString[Numerator[e]], ToString[Denominator[e]]], Head[Unevaluated[e]] === Real, If[MachineNumberQ[e], e, MathematicaMCP`Real[ToString[e, InputForm, NumberMarks -> False], N[Precision[e]]]], StringQ[Unevaluated[e]], e, Head[Unevaluated[e]] === Symbol, MathematicaMCP`Symbol[Context[Unevaluated[e]], SymbolName[Unevaluated[e]]], Head[Unevaluated[e]] === Complex, MathematicaMCP`Normal[tree[Complex], tree @@ {Re[e]}, tree @@ {Im[e]}], AssociationQ[Unevaluated[e]], MathematicaMCP`Normal @@ Prepend[tree /@ Normal[e, Association], tree[Association]], AtomQ[Unevaluated[e]], MathematicaMCP`Atom[ToString[Head[Unevaluated[e]]], ToString[Unevaluated[e], InputForm]], True, MathematicaMCP`Normal @@ Table[Extract[Unevaluated[e], i, tree], {i, 0, Length[Unevaluated[e]]}]]"#;
+
+/// A Wolfram expression as JSON, tagged by `type`.
+///
+/// Numbers are carried as decimal strings so that big integers and
+/// arbitrary-precision reals stay exact.
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExprTree {
+  Integer {
+    value: String
+  },
+  Rational {
+    numerator:   String,
+    denominator: String
+  },
+  Real {
+    value:     String,
+    /// Decimal digits of precision, or `None` for a machine real.
+    precision: Option<f64>
+  },
+  String {
+    value: String
+  },
+  Symbol {
+    /// e.g. `System`` or `Global``.
+    context: String,
+    name:    String
+  },
+  /// `head[arguments...]`; lists, rules and associations are normal
+  /// expressions too.
+  Normal {
+    head:      Box<ExprTree>,
+    arguments: Vec<ExprTree>
+  },
+  /// An atomic object with no meaningful parts, such as a `ByteArray` or an
+  /// `Image`, given by its head and `InputForm`.
+  Atom {
+    head:       String,
+    input_form: String
+  }
+}
+
+impl ExprTree {
+  /// Decode the output of the kernel-side [`ENCODER`].
+  pub fn decode(expr: &Expr) -> anyhow::Result<Self> {
+    match expr.kind() {
+      | ExprKind::Integer(i) => {
+        Ok(Self::Integer {
+          value: i.to_string()
+        })
+      }
+      | ExprKind::Real(r) => {
+        Ok(Self::Real {
+          value:     format!("{:?}", r.into_inner()),
+          precision: None
+        })
+      }
+      | ExprKind::String(s) => {
+        Ok(Self::String {
+          value: s.clone()
+        })
+      }
+      | ExprKind::Normal(_) => Self::decode_marker(expr),
+      | ExprKind::Symbol(_) => Err(anyhow!("unencoded symbol in expression tree: {expr}"))
+    }
+  }
+
+  fn decode_marker(expr: &Expr) -> anyhow::Result<Self> {
+    if let Some([value]) = marker_args(expr, "Integer") {
+      return Ok(Self::Integer {
+        value: string(value)?
+      });
+    }
+    if let Some([numerator, denominator]) = marker_args(expr, "Rational") {
+      return Ok(Self::Rational {
+        numerator:   string(numerator)?,
+        denominator: string(denominator)?
+      });
+    }
+    if let Some([value, precision]) = marker_args(expr, "Real") {
+      let precision = match precision.kind() {
+        | ExprKind::Real(p) => p.into_inner(),
+        | ExprKind::Integer(p) => *p as f64,
+        | _ => return Err(anyhow!("malformed precision in expression tree: {expr}"))
+      };
+      return Ok(Self::Real {
+        value:     string(value)?.replace("*^", "e"),
+        precision: Some(precision)
+      });
+    }
+    if let Some([context, name]) = marker_args(expr, "Symbol") {
+      return Ok(Self::Symbol {
+        context: string(context)?,
+        name:    string(name)?
+      });
+    }
+    if let Some([head, input_form]) = marker_args(expr, "Atom") {
+      return Ok(Self::Atom {
+        head:       string(head)?,
+        input_form: string(input_form)?
+      });
+    }
+    if let Some([head, arguments @ ..]) = marker_args(expr, "Normal") {
+      return Ok(Self::Normal {
+        head:      Box::new(Self::decode(head)?),
+        arguments: arguments.iter().map(Self::decode).collect::<anyhow::Result<_>>()?
+      });
+    }
+    Err(anyhow!("unexpected expression in expression tree: {expr}"))
+  }
+}
+
+/// The arguments of `expr` if its head is the marker symbol
+/// `MathematicaMCP`<name>`.
+pub fn marker_args<'a>(
+  expr: &'a Expr,
+  name: &str
+) -> Option<&'a [Expr]> {
+  let ExprKind::Normal(normal) = expr.kind() else {
+    return None;
+  };
+  is_marker(normal.head(), name).then(|| normal.elements())
+}
+
+/// Whether `expr` is the marker symbol `MathematicaMCP`<name>`.
+pub fn is_marker(
+  expr: &Expr,
+  name: &str
+) -> bool {
+  matches!(expr.kind(), ExprKind::Symbol(s) if s.as_str().strip_prefix(CONTEXT) == Some(name))
+}
+
+pub fn string(expr: &Expr) -> anyhow::Result<String> {
+  match expr.kind() {
+    | ExprKind::String(s) => Ok(s.clone()),
+    | _ => Err(anyhow!("expected a string from the kernel, got: {expr}"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+  use wolfram_expr::Symbol;
+
+  use super::*;
+
+  fn marker(
+    name: &str,
+    args: Vec<Expr>
+  ) -> Expr {
+    Expr::normal(Symbol::new(&format!("{CONTEXT}{name}")), args)
+  }
+
+  fn symbol(
+    context: &str,
+    name: &str
+  ) -> Expr {
+    marker("Symbol", vec![Expr::string(context), Expr::string(name)])
+  }
+
+  #[test]
+  fn decodes_a_normal_expression_with_exact_numbers() {
+    // 1/3 + 2^100 x + 1.5 y
+    let expr = marker("Normal", vec![
+      symbol("System`", "Plus"),
+      marker("Rational", vec![Expr::string("1"), Expr::string("3")]),
+      marker("Normal", vec![
+        symbol("System`", "Times"),
+        marker("Integer", vec![Expr::string("1267650600228229401496703205376")]),
+        symbol("Global`", "x"),
+      ]),
+      marker("Normal", vec![symbol("System`", "Times"), Expr::real(1.5), symbol("Global`", "y")]),
+    ]);
+
+    let tree = serde_json::to_value(ExprTree::decode(&expr).unwrap()).unwrap();
+    assert_eq!(
+      tree,
+      json!({
+        "type": "normal",
+        "head": {"type": "symbol", "context": "System`", "name": "Plus"},
+        "arguments": [
+          {"type": "rational", "numerator": "1", "denominator": "3"},
+          {
+            "type": "normal",
+            "head": {"type": "symbol", "context": "System`", "name": "Times"},
+            "arguments": [
+              {"type": "integer", "value": "1267650600228229401496703205376"},
+              {"type": "symbol", "context": "Global`", "name": "x"}
+            ]
+          },
+          {
+            "type": "normal",
+            "head": {"type": "symbol", "context": "System`", "name": "Times"},
+            "arguments": [
+              {"type": "real", "value": "1.5", "precision": null},
+              {"type": "symbol", "context": "Global`", "name": "y"}
+            ]
+          }
+        ]
+      })
+    );
+  }
+
+  #[test]
+  fn decodes_atoms() {
+    assert_eq!(ExprTree::decode(&Expr::from(42)).unwrap(), ExprTree::Integer {
+      value: "42".to_string()
+    });
+    assert_eq!(ExprTree::decode(&Expr::string("hi")).unwrap(), ExprTree::String {
+      value: "hi".to_string()
+    });
+    assert_eq!(
+      ExprTree::decode(&marker("Real", vec![
+        Expr::string("1.2345678901234567890*^40"),
+        Expr::real(20.)
+      ]))
+      .unwrap(),
+      ExprTree::Real {
+        value:     "1.2345678901234567890e40".to_string(),
+        precision: Some(20.)
+      }
+    );
+    assert_eq!(
+      ExprTree::decode(&marker("Atom", vec![
+        Expr::string("ByteArray"),
+        Expr::string("ByteArray[\"AQI=\"]")
+      ]))
+      .unwrap(),
+      ExprTree::Atom {
+        head:       "ByteArray".to_string(),
+        input_form: "ByteArray[\"AQI=\"]".to_string()
+      }
+    );
+  }
+
+  #[test]
+  fn rejects_unencoded_expressions() {
+    assert!(ExprTree::decode(&Expr::symbol(Symbol::new("System`List"))).is_err());
+    assert!(ExprTree::decode(&Expr::normal(Symbol::new("System`List"), vec![])).is_err());
+    assert!(ExprTree::decode(&marker("Integer", vec![Expr::from(1)])).is_err());
+  }
+}
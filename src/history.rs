@@ -0,0 +1,200 @@
+//! Per-session evaluation history.
+//!
+//! Every evaluation a session runs through [`SessionManager::eval`] is
+//! recorded in order, numbered from 1 like `In[n]`/`Out[n]`, with the full
+//! result (graphics included) so earlier outputs can be retrieved without
+//! recomputing them. Only the latest `session.history_limit` entries are
+//! kept; older ones are dropped but keep their numbers.
+//!
+//! [`SessionManager::eval`]: crate::session::SessionManager::eval
+
+use std::collections::VecDeque;
+use std::sync::{
+  Arc,
+  Mutex
+};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use chrono::{
+  DateTime,
+  Utc
+};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::wolfram::{
+  EvalResult,
+  GraphicsFormat,
+  Part,
+  WolframMessage
+};
+
+/// One evaluation.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+  /// Position in the session's history, from 1.
+  pub n:          usize,
+  pub code:       String,
+  pub started_at: DateTime<Utc>,
+  pub elapsed:    Duration,
+  /// The result, or why the evaluation failed.
+  pub result:     Result<EvalResult, String>
+}
+
+/// A session's evaluations in order. Clones share the same history.
+#[derive(Debug, Clone, Default)]
+pub struct History(Arc<Mutex<Entries>>);
+
+#[derive(Debug, Default)]
+struct Entries {
+  /// The entries still kept, oldest first.
+  kept:     VecDeque<HistoryEntry>,
+  /// Evaluations recorded so far, dropped ones included.
+  recorded: usize,
+  /// Most entries kept; `None` keeps them all.
+  limit:    Option<usize>
+}
+
+impl History {
+  /// A history that keeps only the latest `limit` evaluations.
+  pub fn with_limit(limit: usize) -> Self {
+    Self(Arc::new(Mutex::new(Entries {
+      limit: Some(limit),
+      ..Entries::default()
+    })))
+  }
+
+  /// Append an evaluation and return its number, dropping the oldest entry
+  /// when the history is full.
+  pub fn record(
+    &self,
+    code: &str,
+    started_at: DateTime<Utc>,
+    elapsed: Duration,
+    result: &anyhow::Result<EvalResult>
+  ) -> usize {
+    let mut entries = self.0.lock().unwrap();
+    entries.recorded += 1;
+    let n = entries.recorded;
+    entries.kept.push_back(HistoryEntry {
+      n,
+      code: code.to_string(),
+      started_at,
+      elapsed,
+      result: match result {
+        | Ok(res) => Ok(res.clone()),
+        | Err(e) => Err(e.to_string())
+      }
+    });
+    if let Some(limit) = entries.limit {
+      while entries.kept.len() > limit {
+        entries.kept.pop_front();
+      }
+    }
+    n
+  }
+
+  /// The entries still kept, oldest first.
+  pub fn entries(&self) -> Vec<HistoryEntry> {
+    self.0.lock().unwrap().kept.iter().cloned().collect()
+  }
+
+  /// Evaluations recorded so far, dropped ones included.
+  pub fn recorded(&self) -> usize {
+    self.0.lock().unwrap().recorded
+  }
+
+  /// Entry `n`, counting from 1; a negative `n` counts back from the latest,
+  /// so `-1` is the last evaluation, like `%`.
+  pub fn get(
+    &self,
+    n: i64
+  ) -> anyhow::Result<HistoryEntry> {
+    let entries = self.0.lock().unwrap();
+    let recorded = entries.recorded as i64;
+    let number = if n < 0 {
+      recorded + n + 1
+    } else {
+      n
+    };
+    if n == 0 || !(1..=recorded).contains(&number) {
+      return Err(anyhow!("no evaluation {n} in this session's history ({recorded} so far)"));
+    }
+    let dropped = recorded - entries.kept.len() as i64;
+    if number <= dropped {
+      return Err(anyhow!(
+        "evaluation {number} was dropped from this session's history, which keeps only the last \
+         {} (evaluations {} to {recorded})",
+        entries.kept.len(),
+        dropped + 1
+      ));
+    }
+    Ok(entries.kept[(number - dropped - 1) as usize].clone())
+  }
+}
+
+/// A history entry without the graphics data or the expression tree, for
+/// listing.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct HistorySummary {
+  pub n:              usize,
+  pub code:           String,
+  pub started_at_utc: String,
+  pub elapsed_ms:     u64,
+  /// `None` when the evaluation failed; see `error`.
+  pub output:         Option<String>,
+  pub error:          Option<String>,
+  pub aborted:        bool,
+  pub logs:           Vec<String>,
+  pub messages:       Vec<WolframMessage>,
+  /// The graphics in the result; `mathematica_get_output` returns the images.
+  pub graphics:       Vec<GraphicsSummary>
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct GraphicsSummary {
+  pub position: Vec<Part>,
+  pub head:     String,
+  pub format:   GraphicsFormat
+}
+
+impl HistoryEntry {
+  pub fn summary(&self) -> HistorySummary {
+    let (output, error, aborted, logs, messages, graphics) = match &self.result {
+      | Ok(res) => {
+        (
+          Some(res.output.clone()),
+          None,
+          res.aborted,
+          res.logs.clone(),
+          res.messages.clone(),
+          res
+            .graphics
+            .iter()
+            .map(|g| {
+              GraphicsSummary {
+                position: g.position.clone(),
+                head:     g.head.clone(),
+                format:   g.format
+              }
+            })
+            .collect()
+        )
+      }
+      | Err(e) => (None, Some(e.clone()), false, Vec::new(), Vec::new(), Vec::new())
+    };
+    HistorySummary {
+      n: self.n,
+      code: self.code.clone(),
+      started_at_utc: self.started_at.to_rfc3339(),
+      elapsed_ms: self.elapsed.as_millis() as u64,
+      output,
+      error,
+      aborted,
+      logs,
+      messages,
+      graphics
+    }
+  }
+}
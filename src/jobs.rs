@@ -0,0 +1,152 @@
+//! Evaluations that run in the background.
+//!
+//! A job is an evaluation submitted with [`SessionManager::submit`]: the call
+//! returns a job id at once and the evaluation runs on the session as usual,
+//! so a computation can outlast the MCP host's own request timeout. The job
+//! table keeps each job's status and, once it finishes, its result until the
+//! configured retention period has passed.
+//!
+//! [`SessionManager::submit`]: crate::session::SessionManager::submit
+
+use std::collections::HashMap;
+use std::sync::{
+  Arc,
+  Mutex
+};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use chrono::{
+  DateTime,
+  Utc
+};
+use schemars::JsonSchema;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::wolfram::EvalResult;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+  /// Evaluating, or waiting for the session to finish an earlier evaluation.
+  Running,
+  Completed,
+  /// The evaluation failed; the job's result holds the error.
+  Failed,
+  /// Cancelled; the kernel evaluation was aborted.
+  Cancelled
+}
+
+#[derive(Debug, Clone)]
+pub struct Job {
+  pub id:           String,
+  pub session_id:   String,
+  /// The client that submitted the job, or `None` for an admin.
+  pub owner:        Option<String>,
+  pub code:         String,
+  pub submitted_at: DateTime<Utc>,
+  pub finished_at:  Option<DateTime<Utc>>,
+  /// How long the evaluation took, once it finished.
+  pub elapsed:      Option<Duration>,
+  pub state:        JobState,
+  /// `None` while the job runs.
+  pub result:       Option<Result<EvalResult, String>>,
+  cancel:           CancellationToken
+}
+
+impl Job {
+  pub fn new(
+    session_id: &str,
+    owner: Option<String>,
+    code: &str
+  ) -> Self {
+    Self {
+      id: uuid::Uuid::new_v4().to_string(),
+      session_id: session_id.to_string(),
+      owner,
+      code: code.to_string(),
+      submitted_at: Utc::now(),
+      finished_at: None,
+      elapsed: None,
+      state: JobState::Running,
+      result: None,
+      cancel: CancellationToken::new()
+    }
+  }
+
+  /// Cancelled when the job is.
+  pub fn cancel_token(&self) -> CancellationToken {
+    self.cancel.clone()
+  }
+}
+
+/// Every job not yet past its retention. Clones share the same table.
+#[derive(Debug, Clone, Default)]
+pub struct JobTable(Arc<Mutex<HashMap<String, Job>>>);
+
+impl JobTable {
+  pub fn insert(
+    &self,
+    job: Job
+  ) {
+    self.0.lock().unwrap().insert(job.id.clone(), job);
+  }
+
+  pub fn get(
+    &self,
+    id: &str
+  ) -> anyhow::Result<Job> {
+    self.0.lock().unwrap().get(id).cloned().ok_or_else(|| anyhow!("job not found or expired: {id}"))
+  }
+
+  /// Store a job's result. A cancelled job stays cancelled.
+  pub fn finish(
+    &self,
+    id: &str,
+    result: anyhow::Result<EvalResult>,
+    elapsed: Duration
+  ) {
+    let mut jobs = self.0.lock().unwrap();
+    let Some(job) = jobs.get_mut(id) else {
+      return;
+    };
+    if job.state == JobState::Running {
+      job.state = if result.is_ok() {
+        JobState::Completed
+      } else {
+        JobState::Failed
+      };
+    }
+    job.finished_at = Some(Utc::now());
+    job.elapsed = Some(elapsed);
+    job.result = Some(result.map_err(|e| e.to_string()));
+  }
+
+  /// Cancel a running job; finished jobs are left as they are.
+  pub fn cancel(
+    &self,
+    id: &str
+  ) -> anyhow::Result<Job> {
+    let mut jobs = self.0.lock().unwrap();
+    let job = jobs.get_mut(id).ok_or_else(|| anyhow!("job not found or expired: {id}"))?;
+    if job.state == JobState::Running {
+      job.cancel.cancel();
+      job.state = JobState::Cancelled;
+    }
+    Ok(job.clone())
+  }
+
+  /// Forget jobs that finished more than `retention` ago.
+  pub fn prune(
+    &self,
+    retention: Duration
+  ) {
+    let now = Utc::now();
+    self.0.lock().unwrap().retain(|_, job| {
+      job
+        .finished_at
+        .is_none_or(|finished| (now - finished).to_std().unwrap_or_default() <= retention)
+    });
+  }
+}
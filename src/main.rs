@@ -1,10 +1,22 @@
+mod backend;
+mod config;
+mod export;
+mod expr_tree;
+mod history;
+mod jobs;
 mod mcp;
 mod platform;
+mod pool;
 mod repl;
+mod script;
 mod session;
 mod session_id;
+mod syntax;
 mod wolfram;
 
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
 use clap::{
   Parser,
   Subcommand
@@ -12,23 +24,128 @@ use clap::{
 use tracing::info;
 use tracing_subscriber::EnvFilter;
 
+use crate::config::LoadedConfig;
+
 #[derive(Parser, Debug)]
 #[command(name = "mathematica-mcp-server")]
 #[command(about = "MCP server exposing Wolfram/Mathematica via WSTP", long_about = None)]
 struct Cli {
   #[command(subcommand)]
-  cmd: Option<Command>
+  cmd:                Option<Command>,
+  /// TOML config file (default:
+  /// $MATHEMATICA_MCP_CONFIG, then
+  /// ./mathematica-mcp.toml if present)
+  #[arg(long, global = true, value_name = "PATH")]
+  config:             Option<PathBuf>,
+  /// Idle kernels to keep pre-launched so
+  /// new sessions start instantly (0
+  /// disables the pool)
+  #[arg(long, global = true)]
+  pool_min:           Option<usize>,
+  /// Stop pre-launching once active
+  /// sessions plus pooled kernels reach
+  /// this many
+  #[arg(long, global = true)]
+  pool_max:           Option<usize>,
+  /// Refuse to open more sessions than
+  /// this
+  #[arg(long, global = true)]
+  max_sessions:       Option<usize>,
+  /// How long a session request over the
+  /// limit waits for a free slot (0
+  /// rejects it at once)
+  #[arg(long, global = true, value_name = "SECS")]
+  queue_timeout_secs: Option<u64>,
+  /// Close sessions idle for longer than
+  /// this many seconds
+  #[arg(long, global = true, value_name = "SECS")]
+  idle_timeout_secs:  Option<u64>,
+  /// Eval timeout for calls that do not
+  /// set their own
+  #[arg(long, global = true, value_name = "SECS")]
+  eval_timeout_secs:  Option<u64>,
+  /// Kernel executable (default:
+  /// discovered)
+  #[arg(long, global = true, value_name = "PATH")]
+  kernel_path:        Option<String>,
+  /// Extra kernel argument; repeat for
+  /// several
+  #[arg(long = "kernel-arg", global = true, value_name = "ARG", allow_hyphen_values = true)]
+  kernel_args:        Vec<String>
+}
+
+impl Cli {
+  /// The settings given as flags, for the
+  /// top config layer.
+  fn overrides(&self) -> Vec<(&'static str, toml::Value)> {
+    let int = |v: u64| toml::Value::Integer(v as i64);
+    let mut overrides = Vec::new();
+    if let Some(v) = self.pool_min {
+      overrides.push(("pool.min_idle", int(v as u64)));
+    }
+    if let Some(v) = self.pool_max {
+      overrides.push(("pool.max_kernels", int(v as u64)));
+    }
+    if let Some(v) = self.max_sessions {
+      overrides.push(("session.max_sessions", int(v as u64)));
+    }
+    if let Some(v) = self.queue_timeout_secs {
+      overrides.push(("session.queue_timeout_secs", int(v)));
+    }
+    if let Some(v) = self.idle_timeout_secs {
+      overrides.push(("session.idle_timeout_secs", int(v)));
+    }
+    if let Some(v) = self.eval_timeout_secs {
+      overrides.push(("session.eval_timeout_secs", int(v)));
+    }
+    if let Some(v) = &self.kernel_path {
+      overrides.push(("kernel.path", toml::Value::String(v.clone())));
+    }
+    if !self.kernel_args.is_empty() {
+      let args = self.kernel_args.iter().cloned().map(toml::Value::String).collect();
+      overrides.push(("kernel.args", toml::Value::Array(args)));
+    }
+    overrides
+  }
 }
 
 #[derive(Subcommand, Debug)]
 enum Command {
   /// Run the MCP server over stdio (for
   /// Continue/Claude Desktop/etc.)
-  Serve,
+  Serve {
+    /// Serve streamable HTTP at
+    /// http://<ADDR>/mcp instead of stdio,
+    /// so several clients can share one
+    /// server
+    #[arg(long, value_name = "ADDR")]
+    http:        Option<SocketAddr>,
+    /// Over HTTP, requests with
+    /// `Authorization: Bearer <TOKEN>` may
+    /// see and use every client's sessions
+    #[arg(long, value_name = "TOKEN", env = "MATHEMATICA_MCP_ADMIN_TOKEN", requires = "http")]
+    admin_token: Option<String>
+  },
   /// Interactive REPL that calls the
   /// same tools locally (no MCP host
   /// needed)
-  Repl
+  Repl,
+  /// Run a .wl, .m or .nb file cell by
+  /// cell in a fresh session and print
+  /// each cell's result
+  Run { file: PathBuf },
+  /// Inspect the configuration
+  Config {
+    #[command(subcommand)]
+    cmd: ConfigCommand
+  }
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+  /// Print the effective settings and
+  /// where each one came from
+  Show
 }
 
 fn init_tracing() {
@@ -51,16 +168,40 @@ async fn main() -> anyhow::Result<()> {
   init_tracing();
 
   let cli = Cli::parse();
-  let cmd = cli.cmd.unwrap_or(Command::Serve);
+  let loaded = LoadedConfig::load(cli.config.as_deref(), &cli.overrides())?;
+  let options = loaded.config.session_options();
+  let cmd = cli.cmd.unwrap_or(Command::Serve {
+    http:        None,
+    admin_token: None
+  });
 
   match cmd {
-    | Command::Serve => {
+    | Command::Serve {
+      http: None, ..
+    } => {
       info!("starting MCP server (stdio)");
-      mcp::run_server().await?;
+      mcp::run_server(options).await?;
+    }
+    | Command::Serve {
+      http: Some(addr),
+      admin_token
+    } => {
+      info!("starting MCP server (streamable HTTP)");
+      mcp::run_http_server(options, addr, admin_token).await?;
     }
     | Command::Repl => {
       info!("starting local REPL");
-      repl::run_repl().await?;
+      repl::run_repl(options, &loaded.config.repl.history_path).await?;
+    }
+    | Command::Run {
+      file
+    } => {
+      repl::run_file(options, &file).await?;
+    }
+    | Command::Config {
+      cmd: ConfigCommand::Show
+    } => {
+      print!("{}", loaded.show()?);
     }
   }
 
@@ -1,14 +1,42 @@
+use std::net::SocketAddr;
+use std::sync::{
+  Arc,
+  Mutex
+};
+
+use anyhow::anyhow;
 use chrono::Local;
 use rmcp::handler::server::ServerHandler;
 use rmcp::handler::server::router::tool::ToolRouter;
+use rmcp::handler::server::tool::schema_for_output;
 use rmcp::handler::server::wrapper::Parameters;
 use rmcp::model::{
+  CallToolResult,
+  Content,
+  LoggingLevel,
+  LoggingMessageNotificationParam,
+  ProgressNotificationParam,
+  ProgressToken,
+  ResourceContents,
   ServerCapabilities,
-  ServerInfo
+  ServerInfo,
+  SetLevelRequestParams
+};
+use rmcp::service::{
+  ElicitationError,
+  RequestContext
+};
+use rmcp::transport::streamable_http_server::session::local::LocalSessionManager;
+use rmcp::transport::{
+  StreamableHttpServerConfig,
+  StreamableHttpService,
+  stdio
 };
-use rmcp::transport::stdio;
 use rmcp::{
+  ErrorData,
   Json,
+  Peer,
+  RoleServer,
   serve_server,
   tool,
   tool_handler,
@@ -18,68 +46,751 @@ use serde::{
   Deserialize,
   Serialize
 };
+use tokio_util::sync::CancellationToken;
 
-use crate::session::SessionManager;
-use crate::wolfram;
+use crate::backend::{
+  KernelBackend,
+  WstpBackend
+};
+use crate::export::{
+  self,
+  ExportFormat
+};
+use crate::expr_tree::ExprTree;
+use crate::history::{
+  HistoryEntry,
+  HistorySummary
+};
+use crate::jobs::{
+  Job,
+  JobState
+};
+use crate::pool::PoolStatus;
+use crate::script;
+use crate::session::{
+  Caller,
+  CreateOptions,
+  SessionManager,
+  SessionOptions
+};
+use crate::syntax::{
+  self,
+  SyntaxError
+};
+use crate::wolfram::{
+  self,
+  EvalEvent,
+  EvalOptions,
+  EvalResult,
+  EventChannel,
+  FormattedOutput,
+  GraphicsArtifact,
+  GraphicsFormat,
+  GraphicsOptions,
+  InputChannel,
+  InputKind,
+  InvalidSyntax,
+  OutputFormat,
+  Part,
+  WolframMessage
+};
 
 #[derive(Clone)]
-pub struct MathematicaServer {
-  sessions:    SessionManager,
+pub struct MathematicaServer<B: KernelBackend = WstpBackend> {
+  sessions:    SessionManager<B>,
+  /// The client connection this server instance talks to.
+  client:      Arc<ClientGuard<B>>,
+  /// Requests carrying `Authorization: Bearer <admin_token>` (HTTP only) act
+  /// on every session.
+  admin_token: Option<Arc<str>>,
+  /// The least severe level the client wants `notifications/message` for.
+  log_level:   Arc<Mutex<LoggingLevel>>,
   tool_router: ToolRouter<Self>
 }
 
+/// Identity of one client connection as a session owner. Dropped with the last
+/// server clone serving the connection, i.e. when the client disconnects, at
+/// which point the client's sessions are closed.
+struct ClientGuard<B: KernelBackend> {
+  id:       String,
+  sessions: SessionManager<B>
+}
+
+impl<B: KernelBackend> Drop for ClientGuard<B> {
+  fn drop(&mut self) {
+    if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+      let sessions = self.sessions.clone();
+      let id = std::mem::take(&mut self.id);
+      runtime.spawn(async move { sessions.close_client_sessions(&id).await });
+    }
+  }
+}
+
+/// What a tool call brings besides its parameters: where to ask for input,
+/// where to report progress, and when to give up. Tests leave it all unset.
+#[derive(Default)]
+struct CallContext {
+  input:  Option<InputChannel>,
+  events: Option<EventChannel>,
+  cancel: Option<CancellationToken>
+}
+
 impl MathematicaServer {
-  pub fn new() -> Self {
+  pub fn new(options: SessionOptions) -> Self {
+    Self::with_sessions(SessionManager::new(options))
+  }
+}
+
+impl<B: KernelBackend> MathematicaServer<B> {
+  pub fn with_sessions(sessions: SessionManager<B>) -> Self {
     Self {
-      sessions:    SessionManager::new(),
+      client: Arc::new(ClientGuard {
+        id:       uuid::Uuid::new_v4().to_string(),
+        sessions: sessions.clone()
+      }),
+      sessions,
+      admin_token: None,
+      log_level: Arc::new(Mutex::new(LoggingLevel::Info)),
       tool_router: Self::tool_router()
     }
   }
+
+  pub fn with_admin_token(
+    mut self,
+    admin_token: Option<String>
+  ) -> Self {
+    self.admin_token = admin_token.filter(|t| !t.is_empty()).map(Arc::from);
+    self
+  }
+
+  /// A server instance for another client connection, sharing this one's
+  /// sessions.
+  fn for_new_client(&self) -> Self {
+    Self {
+      admin_token: self.admin_token.clone(),
+      ..Self::with_sessions(self.sessions.clone())
+    }
+  }
+
+  /// Ask for input, report evaluation events and take cancellation over the
+  /// tool call's connection.
+  fn call_context(
+    &self,
+    context: &RequestContext<RoleServer>
+  ) -> CallContext {
+    CallContext {
+      input:  Some(elicit_input(context.peer.clone())),
+      events: Some(relay_events(
+        context.peer.clone(),
+        context.meta.get_progress_token(),
+        self.log_level.clone()
+      )),
+      cancel: Some(context.ct.clone())
+    }
+  }
+
+  /// This connection as a session owner.
+  fn client(&self) -> Caller {
+    Caller::Client(self.client.id.clone())
+  }
+
+  /// Who is behind a tool call: this connection, or an admin if the HTTP
+  /// request presents the admin token.
+  fn caller(
+    &self,
+    context: &RequestContext<RoleServer>
+  ) -> Caller {
+    let presented = context
+      .extensions
+      .get::<axum::http::request::Parts>()
+      .and_then(|parts| parts.headers.get(axum::http::header::AUTHORIZATION))
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| value.strip_prefix("Bearer "));
+    match (&self.admin_token, presented) {
+      | (Some(token), Some(presented))
+        if constant_time_eq(token.as_bytes(), presented.as_bytes()) =>
+      {
+        Caller::Admin
+      }
+      | _ => self.client()
+    }
+  }
+
+  async fn create(
+    &self,
+    caller: &Caller,
+    params: CreateSessionParams
+  ) -> Result<CreateSessionResult, String> {
+    let id = self
+      .sessions
+      .create_session_with(caller, CreateOptions {
+        graphics:      params.graphics.unwrap_or_default(),
+        queue_timeout: params.queue_timeout_seconds.map(std::time::Duration::from_secs),
+        setup_code:    params.setup_code
+      })
+      .await
+      .map_err(|e| e.to_string())?;
+    Ok(CreateSessionResult {
+      session_id: id
+    })
+  }
+
+  async fn close(
+    &self,
+    caller: &Caller,
+    params: CloseSessionParams
+  ) -> Result<CloseSessionResult, String> {
+    if !self.sessions.verify(&params.session_id) {
+      return Err("Invalid session ID.".to_string());
+    }
+    self.sessions.authorize(&params.session_id, caller).await.map_err(|e| e.to_string())?;
+    self.sessions.close_session(&params.session_id).await.map_err(|e| e.to_string())?;
+    Ok(CloseSessionResult {
+      closed:     true,
+      session_id: params.session_id
+    })
+  }
+
+  async fn execute(
+    &self,
+    caller: &Caller,
+    params: ExecuteParams,
+    call: CallContext
+  ) -> Result<(ExecuteResult, Vec<Content>), String> {
+    if !self.sessions.verify(&params.session_id) {
+      return Err("Invalid session ID (malformed or tampered).".to_string());
+    }
+    self.sessions.authorize(&params.session_id, caller).await.map_err(|e| e.to_string())?;
+
+    let mut options = EvalOptions::default();
+    if let Some(spec) = params.output_format {
+      options.output_formats = spec.into_vec();
+    }
+    options.include_expression = params.include_expression;
+    options.graphics = params.graphics.unwrap_or_default();
+    options.input = call.input;
+    options.events = call.events;
+
+    let timeout =
+      params.timeout_seconds.map_or(self.sessions.eval_timeout(), std::time::Duration::from_secs);
+    let started = std::time::Instant::now();
+    let res = match self
+      .sessions
+      .eval_cancellable(&params.session_id, &params.code, &options, timeout, call.cancel.as_ref())
+      .await
+    {
+      | Ok(res) => res,
+      // Point at the user's code rather than the wrapper it was spliced into.
+      | Err(e) if e.is::<InvalidSyntax>() => {
+        return Err(match syntax::check(&self.sessions, &params.session_id, &params.code).await {
+          | Ok(Some(error)) => {
+            format!(
+              "syntax error at line {}, column {} (offset {}):\n{}",
+              error.line, error.column, error.offset, error.snippet
+            )
+          }
+          | _ => e.to_string()
+        });
+      }
+      | Err(e) => return Err(e.to_string())
+    };
+
+    Ok(execute_result(res, started.elapsed().as_millis() as u64))
+  }
+
+  async fn submit(
+    &self,
+    caller: &Caller,
+    params: SubmitParams
+  ) -> Result<SubmitResult, String> {
+    if !self.sessions.verify(&params.session_id) {
+      return Err("Invalid session ID.".to_string());
+    }
+
+    let mut options = EvalOptions::default();
+    if let Some(spec) = params.output_format {
+      options.output_formats = spec.into_vec();
+    }
+    options.include_expression = params.include_expression;
+    options.graphics = params.graphics.unwrap_or_default();
+
+    let timeout =
+      params.timeout_seconds.map_or(self.sessions.eval_timeout(), std::time::Duration::from_secs);
+    let job_id = self
+      .sessions
+      .submit(caller, &params.session_id, &params.code, options, timeout)
+      .await
+      .map_err(|e| e.to_string())?;
+    Ok(SubmitResult {
+      job_id,
+      session_id: params.session_id
+    })
+  }
+
+  fn job_status(
+    &self,
+    caller: &Caller,
+    params: JobParams
+  ) -> Result<JobStatus, String> {
+    let job = self.sessions.job(caller, &params.job_id).map_err(|e| e.to_string())?;
+    Ok(JobStatus::from(&job))
+  }
+
+  fn job_result(
+    &self,
+    caller: &Caller,
+    params: JobParams
+  ) -> Result<(JobResult, Vec<Content>), String> {
+    let job = self.sessions.job(caller, &params.job_id).map_err(|e| e.to_string())?;
+    let res = match job.result {
+      | Some(Ok(res)) => res,
+      | Some(Err(e)) => return Err(format!("job {} failed: {e}", job.id)),
+      | None if job.state == JobState::Cancelled => {
+        return Err(format!("job {} was cancelled and is being aborted", job.id));
+      }
+      | None => {
+        return Err(format!(
+          "job {} is still running; poll mathematica_job_status until it finishes",
+          job.id
+        ));
+      }
+    };
+    let elapsed_ms = job.elapsed.unwrap_or_default().as_millis() as u64;
+    let (result, images) = execute_result(res, elapsed_ms);
+    Ok((
+      JobResult {
+        job_id: job.id,
+        state: job.state,
+        result
+      },
+      images
+    ))
+  }
+
+  fn job_cancel(
+    &self,
+    caller: &Caller,
+    params: JobParams
+  ) -> Result<JobStatus, String> {
+    let job = self.sessions.cancel_job(caller, &params.job_id).map_err(|e| e.to_string())?;
+    Ok(JobStatus::from(&job))
+  }
+
+  async fn run(
+    &self,
+    caller: &Caller,
+    params: RunFileParams,
+    call: CallContext
+  ) -> Result<(RunFileResult, Vec<Content>), String> {
+    if !self.sessions.verify(&params.session_id) {
+      return Err("Invalid session ID.".to_string());
+    }
+    self.sessions.authorize(&params.session_id, caller).await.map_err(|e| e.to_string())?;
+
+    let options = EvalOptions {
+      graphics: params.graphics.unwrap_or_default(),
+      input: call.input,
+      events: call.events,
+      ..EvalOptions::default()
+    };
+    let timeout =
+      params.timeout_seconds.map_or(self.sessions.eval_timeout(), std::time::Duration::from_secs);
+    let started = std::time::Instant::now();
+    let path = std::path::Path::new(&params.path);
+    let run = script::run_file(
+      &self.sessions,
+      &params.session_id,
+      path,
+      &options,
+      timeout,
+      call.cancel.as_ref()
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let completed =
+      run.cells.len() == run.total && run.cells.last().is_none_or(|cell| !cell.failed());
+    let (cells, content) = cell_results(run.cells);
+    Ok((
+      RunFileResult {
+        path: params.path,
+        total_cells: run.total,
+        completed,
+        cells,
+        elapsed_ms: started.elapsed().as_millis() as u64
+      },
+      content
+    ))
+  }
+
+  async fn batch(
+    &self,
+    caller: &Caller,
+    params: ExecuteBatchParams,
+    call: CallContext
+  ) -> Result<(BatchResult, Vec<Content>), String> {
+    if !self.sessions.verify(&params.session_id) {
+      return Err("Invalid session ID.".to_string());
+    }
+    self.sessions.authorize(&params.session_id, caller).await.map_err(|e| e.to_string())?;
+
+    let mut options = EvalOptions::default();
+    if let Some(spec) = params.output_format {
+      options.output_formats = spec.into_vec();
+    }
+    options.include_expression = params.include_expression;
+    options.graphics = params.graphics.unwrap_or_default();
+    options.input = call.input;
+    options.events = call.events;
+
+    let timeout =
+      params.timeout_seconds.map_or(self.sessions.eval_timeout(), std::time::Duration::from_secs);
+    let stop_on_error = params.stop_on_error.unwrap_or(true);
+    let started = std::time::Instant::now();
+    let runs = script::run_cells(
+      &self.sessions,
+      &params.session_id,
+      &params.cells,
+      &options,
+      timeout,
+      call.cancel.as_ref(),
+      |cell| stop_on_error && cell.errored()
+    )
+    .await;
+
+    let stopped = runs.len() < params.cells.len();
+    let (cells, content) = cell_results(runs);
+    Ok((
+      BatchResult {
+        stopped,
+        cells,
+        elapsed_ms: started.elapsed().as_millis() as u64
+      },
+      content
+    ))
+  }
+
+  async fn history(
+    &self,
+    caller: &Caller,
+    params: GetHistoryParams
+  ) -> Result<HistoryResult, String> {
+    if !self.sessions.verify(&params.session_id) {
+      return Err("Invalid session ID.".to_string());
+    }
+    self.sessions.authorize(&params.session_id, caller).await.map_err(|e| e.to_string())?;
+    let history = self.sessions.history(&params.session_id).await.map_err(|e| e.to_string())?;
+    let entries = history.entries();
+    Ok(HistoryResult {
+      total:   history.recorded(),
+      entries: entries
+        .iter()
+        .filter(|e| e.n >= params.from.unwrap_or(1))
+        .take(params.limit.unwrap_or(usize::MAX))
+        .map(HistoryEntry::summary)
+        .collect()
+    })
+  }
+
+  async fn output(
+    &self,
+    caller: &Caller,
+    params: GetOutputParams
+  ) -> Result<(OutputResult, Vec<Content>), String> {
+    if !self.sessions.verify(&params.session_id) {
+      return Err("Invalid session ID.".to_string());
+    }
+    self.sessions.authorize(&params.session_id, caller).await.map_err(|e| e.to_string())?;
+    let history = self.sessions.history(&params.session_id).await.map_err(|e| e.to_string())?;
+    let entry = history.get(params.n).map_err(|e| e.to_string())?;
+    let res = entry.result.map_err(|e| format!("evaluation {} failed: {e}", entry.n))?;
+    let (result, images) = execute_result(res, entry.elapsed.as_millis() as u64);
+    Ok((
+      OutputResult {
+        n: entry.n,
+        code: entry.code,
+        started_at_utc: entry.started_at.to_rfc3339(),
+        result
+      },
+      images
+    ))
+  }
+
+  async fn export(
+    &self,
+    caller: &Caller,
+    params: ExportSessionParams
+  ) -> Result<(ExportSessionResult, Vec<Content>), String> {
+    if !self.sessions.verify(&params.session_id) {
+      return Err("Invalid session ID.".to_string());
+    }
+    self.sessions.authorize(&params.session_id, caller).await.map_err(|e| e.to_string())?;
+    let path = params.path.as_deref().map(std::path::Path::new);
+    let format = match (params.format, path) {
+      | (Some(format), _) => format,
+      | (None, Some(path)) => ExportFormat::from_path(path).map_err(|e| e.to_string())?,
+      | (None, None) => ExportFormat::Notebook
+    };
+    let exported = export::export_session(&self.sessions, &params.session_id, format, path)
+      .await
+      .map_err(|e| e.to_string())?;
+    let content = exported
+      .content
+      .map(|text| {
+        Content::resource(ResourceContents::TextResourceContents {
+          uri: format!("mathematica-mcp://export/{}.{}", params.session_id, format.extension()),
+          mime_type: Some(format.mime_type().to_string()),
+          text,
+          meta: None
+        })
+      })
+      .into_iter()
+      .collect();
+    Ok((
+      ExportSessionResult {
+        format: exported.format,
+        cells:  exported.cells,
+        path:   exported.path.map(|p| p.display().to_string())
+      },
+      content
+    ))
+  }
+
+  async fn syntax_check(
+    &self,
+    caller: &Caller,
+    params: CheckSyntaxParams
+  ) -> Result<CheckSyntaxResult, String> {
+    if !self.sessions.verify(&params.session_id) {
+      return Err("Invalid session ID.".to_string());
+    }
+    self.sessions.authorize(&params.session_id, caller).await.map_err(|e| e.to_string())?;
+
+    let error = syntax::check(&self.sessions, &params.session_id, &params.code)
+      .await
+      .map_err(|e| e.to_string())?;
+    Ok(CheckSyntaxResult {
+      valid: error.is_none(),
+      error
+    })
+  }
+
+  async fn finance(
+    &self,
+    caller: &Caller,
+    params: FinanceParams,
+    call: CallContext
+  ) -> Result<(FinanceResult, Vec<Content>), String> {
+    if !self.sessions.verify(&params.session_id) {
+      return Err("Invalid session ID.".to_string());
+    }
+    self.sessions.authorize(&params.session_id, caller).await.map_err(|e| e.to_string())?;
+
+    let code = wolfram::build_financial_data_code(
+      &params.symbol,
+      params.property.as_deref(),
+      params.start_date.as_deref(),
+      params.end_date.as_deref(),
+      params.interval.as_deref()
+    )
+    .map_err(|e| e.to_string())?;
+
+    let timeout =
+      params.timeout_seconds.map_or(self.sessions.eval_timeout(), std::time::Duration::from_secs);
+    let started = std::time::Instant::now();
+    let res = self
+      .sessions
+      .eval_cancellable(
+        &params.session_id,
+        &code,
+        &EvalOptions::default(),
+        timeout,
+        call.cancel.as_ref()
+      )
+      .await
+      .map_err(|e| e.to_string())?;
+
+    let (graphics, images) = image_content(res.graphics);
+    Ok((
+      FinanceResult {
+        wolfram_code: code,
+        output: res.output,
+        logs: res.logs,
+        graphics,
+        messages: res.messages,
+        aborted: res.aborted,
+        elapsed_ms: started.elapsed().as_millis() as u64
+      },
+      images
+    ))
+  }
 }
 
 #[tool_router]
-impl MathematicaServer {
+impl<B: KernelBackend> MathematicaServer<B> {
   #[tool(
     name = "mathematica_create_session",
     description = "Launch a new Wolfram kernel session. Returns a session id that must be used \
-                   for subsequent calls. Sessions idle for more than 30 minutes are automatically \
-                   closed."
+                   for subsequent calls. Uses a pre-warmed kernel from the pool when one is \
+                   available. Idle sessions are automatically closed (after 30 minutes by \
+                   default). `graphics` sets the session's default graphics export (format PNG, \
+                   SVG, PDF or JPEG; resolution in dpi; image_size; background). The session \
+                   belongs to this client and is closed when the client disconnects; it does not \
+                   survive a reconnect, so a client that reconnects must create a new one. When \
+                   the server's session limit is reached the request waits up to \
+                   queue_timeout_seconds for a session to close, then fails with an error \
+                   starting `capacity:`; a kernel that cannot get a Wolfram license fails with \
+                   `license_exhausted:`. `setup_code` runs once the kernel is up and is replayed \
+                   whenever a dead kernel is restarted."
   )]
-  async fn create_session(&self) -> Result<Json<CreateSessionResult>, String> {
-    let id = self.sessions.create_session().await.map_err(|e| e.to_string())?;
-    Ok(Json(CreateSessionResult {
-      session_id: id
-    }))
+  async fn create_session(
+    &self,
+    context: RequestContext<RoleServer>,
+    Parameters(params): Parameters<CreateSessionParams>
+  ) -> Result<Json<CreateSessionResult>, String> {
+    self.create(&self.caller(&context), params).await.map(Json)
   }
 
   #[tool(
     name = "mathematica_execute_code",
+    output_schema = schema_for_output::<ExecuteResult>().expect("ExecuteResult is an object"),
     description = "Evaluate Wolfram Language code in a specific session. Returns the result as a \
-                   string, captures any printed logs (from Print[] or Message[]), and detects \
-                   graphics objects (returning them as Base64 PNGs)."
+                   string, captures Print[] output in `logs` and kernel messages (symbol, tag, \
+                   text, arguments) in `messages`, and detects graphics objects, returning them \
+                   as image content (PNG unless `graphics` or the session says otherwise) \
+                   alongside the structured result. output_format selects InputForm (default), \
+                   FullForm, OutputForm, TeXForm, MathML, TraditionalForm or StandardForm, or a \
+                   list of them. include_expression adds the result as a typed JSON tree (heads, \
+                   arguments, exact integers and rationals, reals with precision, strings, \
+                   symbols with contexts). Input[] and InputString[] are forwarded to the user \
+                   as elicitation requests. Print[] lines are also sent as log notifications \
+                   while the code runs, and MathematicaMCP`Progress[done, total, message] sends \
+                   progress notifications. If timeout_seconds expires, or the client cancels the \
+                   request, the evaluation is aborted, `aborted` is true, and the session stays \
+                   usable. Code that does not parse fails with the line and column of the \
+                   syntax error."
   )]
   async fn execute_code(
     &self,
+    context: RequestContext<RoleServer>,
     Parameters(params): Parameters<ExecuteParams>
-  ) -> Result<Json<ExecuteResult>, String> {
-    if !self.sessions.verify(&params.session_id) {
-      return Err("Invalid session ID (malformed or tampered).".to_string());
-    }
+  ) -> Result<CallToolResult, ErrorData> {
+    with_images(self.execute(&self.caller(&context), params, self.call_context(&context)).await)
+  }
 
-    let timeout = std::time::Duration::from_secs(params.timeout_seconds.unwrap_or(60));
-    let started = std::time::Instant::now();
-    let res = self
-      .sessions
-      .eval(&params.session_id, &params.code, timeout)
-      .await
-      .map_err(|e| e.to_string())?;
+  #[tool(
+    name = "mathematica_submit",
+    description = "Start evaluating Wolfram Language code in a session in the background and \
+                   return a job id at once, for computations that may outlast the MCP request \
+                   timeout. Takes the same options as mathematica_execute_code; timeout_seconds \
+                   still bounds the evaluation, so set it generously. The job queues behind any \
+                   other evaluation on the session. Poll mathematica_job_status, then fetch the \
+                   result with mathematica_job_result. Input[] is not available to jobs."
+  )]
+  async fn submit_job(
+    &self,
+    context: RequestContext<RoleServer>,
+    Parameters(params): Parameters<SubmitParams>
+  ) -> Result<Json<SubmitResult>, String> {
+    self.submit(&self.caller(&context), params).await.map(Json)
+  }
 
-    Ok(Json(ExecuteResult {
-      output:     res.output,
-      logs:       res.logs,
-      graphics:   res.graphics,
-      elapsed_ms: started.elapsed().as_millis() as u64
-    }))
+  #[tool(
+    name = "mathematica_job_status",
+    description = "Report a job's state (running, completed, failed or cancelled), when it was \
+                   submitted and finished, and how long it took. Finished jobs are kept for the \
+                   configured retention period (an hour by default)."
+  )]
+  async fn get_job_status(
+    &self,
+    context: RequestContext<RoleServer>,
+    Parameters(params): Parameters<JobParams>
+  ) -> Result<Json<JobStatus>, String> {
+    self.job_status(&self.caller(&context), params).map(Json)
+  }
+
+  #[tool(
+    name = "mathematica_job_result",
+    output_schema = schema_for_output::<JobResult>().expect("JobResult is an object"),
+    description = "Return a finished job's result, shaped like mathematica_execute_code's with \
+                   graphics as image content. Fails while the job is still running, or with the \
+                   job's error if it failed. A cancelled job's result is `aborted`."
+  )]
+  async fn get_job_result(
+    &self,
+    context: RequestContext<RoleServer>,
+    Parameters(params): Parameters<JobParams>
+  ) -> Result<CallToolResult, ErrorData> {
+    with_images(self.job_result(&self.caller(&context), params))
+  }
+
+  #[tool(
+    name = "mathematica_job_cancel",
+    description = "Cancel a running job, aborting its kernel evaluation; the session stays \
+                   usable. Finished jobs are left as they are. Returns the job's status."
+  )]
+  async fn cancel_job(
+    &self,
+    context: RequestContext<RoleServer>,
+    Parameters(params): Parameters<JobParams>
+  ) -> Result<Json<JobStatus>, String> {
+    self.job_cancel(&self.caller(&context), params).map(Json)
+  }
+
+  #[tool(
+    name = "mathematica_execute_batch",
+    output_schema = schema_for_output::<BatchResult>().expect("BatchResult is an object"),
+    description = "Evaluate several code cells in order in one session, in a single call, and \
+                   return one result per cell, shaped like mathematica_execute_code's (graphics \
+                   as image content, numbered across the whole batch). Use it for a run of small \
+                   dependent evaluations. timeout_seconds, output_format, include_expression \
+                   and graphics apply to every cell. With stop_on_error (the default) the batch \
+                   stops after the first cell that fails, is aborted or issues kernel messages; \
+                   `stopped` says whether later cells were skipped. Each cell is recorded in the \
+                   session's history."
+  )]
+  async fn execute_batch(
+    &self,
+    context: RequestContext<RoleServer>,
+    Parameters(params): Parameters<ExecuteBatchParams>
+  ) -> Result<CallToolResult, ErrorData> {
+    with_images(self.batch(&self.caller(&context), params, self.call_context(&context)).await)
+  }
+
+  #[tool(
+    name = "mathematica_run_file",
+    output_schema = schema_for_output::<RunFileResult>().expect("RunFileResult is an object"),
+    description = "Run a Wolfram Language file from the server's disk in a session, cell by cell: \
+                   each top-level expression of a .wl or .m package, or each input cell of a \
+                   .nb notebook, is evaluated in order and gets its own result with output, \
+                   logs, messages, graphics (as image content) and timing. timeout_seconds \
+                   applies to each cell. The run stops at the first cell that fails or is \
+                   aborted; `completed` says whether every cell ran. The cells are recorded in \
+                   the session's history."
+  )]
+  async fn run_file(
+    &self,
+    context: RequestContext<RoleServer>,
+    Parameters(params): Parameters<RunFileParams>
+  ) -> Result<CallToolResult, ErrorData> {
+    with_images(self.run(&self.caller(&context), params, self.call_context(&context)).await)
+  }
+
+  #[tool(
+    name = "mathematica_check_syntax",
+    description = "Check whether Wolfram Language code parses, without evaluating it. Returns \
+                   `valid`, and for invalid code the `error`: the character offset (from 0), line \
+                   and column (from 1) where parsing fails, the offending line with a caret under \
+                   the error, and whether the code is merely `incomplete` (an unclosed bracket or \
+                   string, or a trailing operator). Use it to vet code, such as code with side \
+                   effects, before running it."
+  )]
+  async fn check_syntax(
+    &self,
+    context: RequestContext<RoleServer>,
+    Parameters(params): Parameters<CheckSyntaxParams>
+  ) -> Result<Json<CheckSyntaxResult>, String> {
+    self.syntax_check(&self.caller(&context), params).await.map(Json)
   }
 
   #[tool(
@@ -88,30 +799,89 @@ impl MathematicaServer {
   )]
   async fn close_session(
     &self,
+    context: RequestContext<RoleServer>,
     Parameters(params): Parameters<CloseSessionParams>
   ) -> Result<Json<CloseSessionResult>, String> {
-    if !self.sessions.verify(&params.session_id) {
-      return Err("Invalid session ID.".to_string());
-    }
-    self.sessions.close_session(&params.session_id).await.map_err(|e| e.to_string())?;
-    Ok(Json(CloseSessionResult {
-      closed:     true,
-      session_id: params.session_id
-    }))
+    self.close(&self.caller(&context), params).await.map(Json)
   }
 
   #[tool(
     name = "mathematica_list_sessions",
-    description = "Return all active sessions, their creation times, and how long they have been \
-                   idle."
+    description = "Return this client's active sessions, their creation times, how long they have \
+                   been idle, their state (starting, ready, busy or dead), and how many times \
+                   their kernel died and was restarted."
   )]
-  async fn list_sessions(&self) -> Result<Json<ListSessionsResult>, String> {
-    let sessions = self.sessions.list_sessions().await;
+  async fn list_sessions(
+    &self,
+    context: RequestContext<RoleServer>
+  ) -> Result<Json<ListSessionsResult>, String> {
+    let sessions = self.sessions.list_sessions_for(&self.caller(&context)).await;
     Ok(Json(ListSessionsResult {
       sessions
     }))
   }
 
+  #[tool(
+    name = "mathematica_get_history",
+    description = "List a session's evaluations in order, numbered from 1 like In[n]/Out[n]: the \
+                   code, output or error, Print logs, messages, the graphics in the result \
+                   (without their data), when it started and how long it took. `from` skips \
+                   earlier entries and `limit` caps how many are returned. Only the latest \
+                   evaluations are kept (session.history_limit); older ones are dropped."
+  )]
+  async fn get_history(
+    &self,
+    context: RequestContext<RoleServer>,
+    Parameters(params): Parameters<GetHistoryParams>
+  ) -> Result<Json<HistoryResult>, String> {
+    self.history(&self.caller(&context), params).await.map(Json)
+  }
+
+  #[tool(
+    name = "mathematica_get_output",
+    output_schema = schema_for_output::<OutputResult>().expect("OutputResult is an object"),
+    description = "Return evaluation n of a session in full, like Out[n], without recomputing it: \
+                   the same result mathematica_execute_code returned, graphics included as \
+                   image content. Negative n counts back from the latest evaluation (-1 is the \
+                   last)."
+  )]
+  async fn get_output(
+    &self,
+    context: RequestContext<RoleServer>,
+    Parameters(params): Parameters<GetOutputParams>
+  ) -> Result<CallToolResult, ErrorData> {
+    with_images(self.output(&self.caller(&context), params).await)
+  }
+
+  #[tool(
+    name = "mathematica_export_session",
+    output_schema = schema_for_output::<ExportSessionResult>()
+      .expect("ExportSessionResult is an object"),
+    description = "Export a session's evaluation history as a Wolfram notebook (.nb), a Jupyter \
+                   notebook (.ipynb) or Markdown (.md): each input becomes an input cell \
+                   followed by its Print output, messages, result and graphics as images. The \
+                   export is written to `path` on the server (Markdown images go to a \
+                   `<name>_files` directory beside it); without a path it is returned as a \
+                   resource, with Markdown images inlined. The format defaults to the one \
+                   `path`'s extension implies. Exporting does not add to the history."
+  )]
+  async fn export_session(
+    &self,
+    context: RequestContext<RoleServer>,
+    Parameters(params): Parameters<ExportSessionParams>
+  ) -> Result<CallToolResult, ErrorData> {
+    with_images(self.export(&self.caller(&context), params).await)
+  }
+
+  #[tool(
+    name = "mathematica_pool_status",
+    description = "Report the pre-warmed kernel pool: idle and launching kernels, active \
+                   sessions, and the configured min/max sizes."
+  )]
+  async fn pool_status(&self) -> Result<Json<PoolStatus>, String> {
+    Ok(Json(self.sessions.pool_status().await))
+  }
+
   #[tool(
     name = "mathematica_time",
     description = "Return current local and UTC time in RFC3339 format."
@@ -126,43 +896,25 @@ impl MathematicaServer {
 
   #[tool(
     name = "mathematica_get_finance",
+    output_schema = schema_for_output::<FinanceResult>().expect("FinanceResult is an object"),
     description = "High-level helper for FinancialData[...]. Automatically builds and evaluates \
                    the appropriate Wolfram Language code in the specified session."
   )]
   async fn get_finance(
     &self,
+    context: RequestContext<RoleServer>,
     Parameters(params): Parameters<FinanceParams>
-  ) -> Result<Json<FinanceResult>, String> {
-    if !self.sessions.verify(&params.session_id) {
-      return Err("Invalid session ID.".to_string());
-    }
-
-    let code = wolfram::build_financial_data_code(
-      &params.symbol,
-      params.property.as_deref(),
-      params.start_date.as_deref(),
-      params.end_date.as_deref(),
-      params.interval.as_deref()
-    )
-    .map_err(|e| e.to_string())?;
-
-    let timeout = std::time::Duration::from_secs(params.timeout_seconds.unwrap_or(60));
-    let started = std::time::Instant::now();
-    let res =
-      self.sessions.eval(&params.session_id, &code, timeout).await.map_err(|e| e.to_string())?;
-
-    Ok(Json(FinanceResult {
-      wolfram_code: code,
-      output:       res.output,
-      logs:         res.logs,
-      graphics:     res.graphics,
-      elapsed_ms:   started.elapsed().as_millis() as u64
-    }))
+  ) -> Result<CallToolResult, ErrorData> {
+    let call = CallContext {
+      cancel: Some(context.ct.clone()),
+      ..CallContext::default()
+    };
+    with_images(self.finance(&self.caller(&context), params, call).await)
   }
 }
 
 #[tool_handler(router = self.tool_router)]
-impl ServerHandler for MathematicaServer {
+impl<B: KernelBackend> ServerHandler for MathematicaServer<B> {
   fn get_info(&self) -> ServerInfo {
     ServerInfo {
       instructions: Some(
@@ -170,17 +922,113 @@ impl ServerHandler for MathematicaServer {
          mathematica_execute_code / mathematica_get_finance, then mathematica_close_session."
           .to_string()
       ),
-      capabilities: ServerCapabilities::builder().enable_tools().build(),
+      capabilities: ServerCapabilities::builder().enable_tools().enable_logging().build(),
       ..Default::default()
     }
   }
+
+  async fn set_level(
+    &self,
+    request: SetLevelRequestParams,
+    _context: RequestContext<RoleServer>
+  ) -> Result<(), ErrorData> {
+    *self.log_level.lock().unwrap() = request.level;
+    Ok(())
+  }
 }
 
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
 pub struct ExecuteParams {
+  pub session_id:         String,
+  pub code:               String,
+  pub timeout_seconds:    Option<u64>,
+  /// One format or a list of formats (default `InputForm`). The first is
+  /// returned as `output`; all of them appear in `outputs`.
+  pub output_format:      Option<OutputFormatSpec>,
+  /// Also return the result as a typed JSON tree in `expression`.
+  #[serde(default)]
+  pub include_expression: bool,
+  /// Graphics export for this call. Unset fields fall back to the session's
+  /// defaults.
+  pub graphics:           Option<GraphicsOptions>
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct SubmitParams {
+  pub session_id:         String,
+  pub code:               String,
+  pub timeout_seconds:    Option<u64>,
+  pub output_format:      Option<OutputFormatSpec>,
+  #[serde(default)]
+  pub include_expression: bool,
+  pub graphics:           Option<GraphicsOptions>
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct JobParams {
+  pub job_id: String
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ExecuteBatchParams {
+  pub session_id:         String,
+  /// Code cells, evaluated in order.
+  pub cells:              Vec<String>,
+  /// Stop after the first cell that fails, is aborted or issues messages
+  /// (default true).
+  pub stop_on_error:      Option<bool>,
+  /// Timeout for each cell.
+  pub timeout_seconds:    Option<u64>,
+  pub output_format:      Option<OutputFormatSpec>,
+  #[serde(default)]
+  pub include_expression: bool,
+  pub graphics:           Option<GraphicsOptions>
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct RunFileParams {
   pub session_id:      String,
-  pub code:            String,
-  pub timeout_seconds: Option<u64>
+  /// A .wl, .m or .nb file on the server.
+  pub path:            String,
+  /// Timeout for each cell.
+  pub timeout_seconds: Option<u64>,
+  /// Graphics export for the run. Unset fields fall back to the session's
+  /// defaults.
+  pub graphics:        Option<GraphicsOptions>
+}
+
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct CreateSessionParams {
+  /// Default graphics export for every evaluation in the session.
+  pub graphics:              Option<GraphicsOptions>,
+  /// When the server is at its session limit, wait up to this long for a
+  /// session to close instead of the configured queue timeout.
+  pub queue_timeout_seconds: Option<u64>,
+  /// Wolfram Language code to run when the session starts (package loads,
+  /// definitions, ...). It is replayed if the kernel dies and is restarted.
+  pub setup_code:            Option<String>
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+#[serde(untagged)]
+pub enum OutputFormatSpec {
+  One(OutputFormat),
+  Many(Vec<OutputFormat>)
+}
+
+impl OutputFormatSpec {
+  fn into_vec(self) -> Vec<OutputFormat> {
+    match self {
+      | Self::One(format) => vec![format],
+      | Self::Many(formats) => formats
+    }
+  }
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct CheckSyntaxParams {
+  pub session_id: String,
+  pub code:       String
 }
 
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
@@ -188,6 +1036,31 @@ pub struct CloseSessionParams {
   pub session_id: String
 }
 
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetHistoryParams {
+  pub session_id: String,
+  /// First evaluation to return (default 1).
+  pub from:       Option<usize>,
+  /// Most evaluations to return (default all).
+  pub limit:      Option<usize>
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetOutputParams {
+  pub session_id: String,
+  /// Evaluation number from 1, or negative to count back from the latest.
+  pub n:          i64
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ExportSessionParams {
+  pub session_id: String,
+  /// Defaults to the format `path`'s extension implies, or `notebook`.
+  pub format:     Option<ExportFormat>,
+  /// File to write on the server. Omit to get the export back as a resource.
+  pub path:       Option<String>
+}
+
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
 pub struct FinanceParams {
   pub session_id:      String,
@@ -207,11 +1080,127 @@ pub struct CreateSessionResult {
 #[derive(Debug, Serialize, schemars::JsonSchema)]
 pub struct ExecuteResult {
   pub output:     String,
+  pub outputs:    Vec<FormattedOutput>,
   pub logs:       Vec<String>,
-  pub graphics:   Option<String>,
+  pub graphics:   Vec<ImageRef>,
+  pub messages:   Vec<WolframMessage>,
+  /// The evaluation timed out or was cancelled, and the kernel evaluation was
+  /// aborted.
+  pub aborted:    bool,
+  /// The result as a typed tree, when `include_expression` was set.
+  pub expression: Option<ExprTree>,
+  pub elapsed_ms: u64
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct RunFileResult {
+  pub path:        String,
+  /// Top-level expressions or input cells in the file.
+  pub total_cells: usize,
+  /// Every cell ran without failing or being aborted.
+  pub completed:   bool,
+  /// The cells that ran, in order.
+  pub cells:       Vec<CellResult>,
+  pub elapsed_ms:  u64
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct SubmitResult {
+  pub job_id:     String,
+  pub session_id: String
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct JobStatus {
+  pub job_id:           String,
+  pub session_id:       String,
+  pub code:             String,
+  pub state:            JobState,
+  pub submitted_at_utc: String,
+  pub finished_at_utc:  Option<String>,
+  /// How long the evaluation took, once it finished.
+  pub elapsed_ms:       Option<u64>,
+  /// Why the job failed.
+  pub error:            Option<String>
+}
+
+impl From<&Job> for JobStatus {
+  fn from(job: &Job) -> Self {
+    Self {
+      job_id:           job.id.clone(),
+      session_id:       job.session_id.clone(),
+      code:             job.code.clone(),
+      state:            job.state,
+      submitted_at_utc: job.submitted_at.to_rfc3339(),
+      finished_at_utc:  job.finished_at.map(|t| t.to_rfc3339()),
+      elapsed_ms:       job.elapsed.map(|e| e.as_millis() as u64),
+      error:            job.result.as_ref().and_then(|r| r.as_ref().err().cloned())
+    }
+  }
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct JobResult {
+  pub job_id: String,
+  pub state:  JobState,
+  #[serde(flatten)]
+  pub result: ExecuteResult
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct BatchResult {
+  /// A cell stopped the batch before every cell ran.
+  pub stopped:    bool,
+  /// The cells that ran, in order.
+  pub cells:      Vec<CellResult>,
   pub elapsed_ms: u64
 }
 
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct CellResult {
+  /// Position in the file or batch, from 1.
+  pub index:  usize,
+  pub code:   String,
+  /// The result, as mathematica_execute_code returns it; absent when the
+  /// cell failed.
+  #[serde(flatten)]
+  pub result: Option<ExecuteResult>,
+  pub error:  Option<String>
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct HistoryResult {
+  /// Evaluations in the session so far, including any dropped from the
+  /// history.
+  pub total:   usize,
+  pub entries: Vec<HistorySummary>
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct OutputResult {
+  pub n:              usize,
+  pub code:           String,
+  pub started_at_utc: String,
+  #[serde(flatten)]
+  pub result:         ExecuteResult
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct ExportSessionResult {
+  pub format: ExportFormat,
+  /// Number of evaluations exported.
+  pub cells:  usize,
+  /// Absolute path of the written file; `None` when returned as a resource.
+  pub path:   Option<String>
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct CheckSyntaxResult {
+  pub valid: bool,
+  /// Where parsing fails, for invalid code.
+  pub error: Option<SyntaxError>
+}
+
 #[derive(Debug, Serialize, schemars::JsonSchema)]
 pub struct CloseSessionResult {
   pub closed:     bool,
@@ -234,17 +1223,1231 @@ pub struct FinanceResult {
   pub wolfram_code: String,
   pub output:       String,
   pub logs:         Vec<String>,
-  pub graphics:     Option<String>,
+  pub graphics:     Vec<ImageRef>,
+  pub messages:     Vec<WolframMessage>,
+  pub aborted:      bool,
   pub elapsed_ms:   u64
 }
 
-pub async fn run_server() -> anyhow::Result<()> {
-  let server = MathematicaServer::new();
+/// Where a rendered graphic went. The Base64 data is sent as an MCP content
+/// block rather than inlined in the JSON: an image block for PNG, JPEG and
+/// SVG, so multimodal clients can display it directly, and an embedded
+/// resource for PDF.
+#[derive(Debug, Serialize, schemars::JsonSchema, Clone, PartialEq)]
+pub struct ImageRef {
+  /// Part specification of the graphic within the result; `[]` is the result
+  /// itself.
+  pub position:      Vec<Part>,
+  pub head:          String,
+  pub mime_type:     String,
+  /// Index of the block in the tool result's `content`.
+  pub content_index: usize
+}
+
+/// Turn graphics artifacts into content blocks. They follow the text block
+/// that mirrors the structured result, so indices start at 1.
+fn image_content(graphics: Vec<GraphicsArtifact>) -> (Vec<ImageRef>, Vec<Content>) {
+  graphics
+    .into_iter()
+    .enumerate()
+    .map(|(i, artifact)| {
+      let content_index = i + 1;
+      let mime_type = artifact.format.mime_type();
+      let content = match artifact.format {
+        | GraphicsFormat::Pdf => {
+          Content::resource(ResourceContents::BlobResourceContents {
+            uri:       format!("mathematica-mcp://graphics/{content_index}.pdf"),
+            mime_type: Some(mime_type.to_string()),
+            blob:      artifact.data,
+            meta:      None
+          })
+        }
+        | _ => Content::image(artifact.data, mime_type)
+      };
+      let image = ImageRef {
+        position: artifact.position,
+        head: artifact.head,
+        mime_type: mime_type.to_string(),
+        content_index
+      };
+      (image, content)
+    })
+    .unzip()
+}
+
+/// The tool result for an evaluation, with its graphics as content blocks.
+fn execute_result(
+  res: EvalResult,
+  elapsed_ms: u64
+) -> (ExecuteResult, Vec<Content>) {
+  let (graphics, images) = image_content(res.graphics);
+  (
+    ExecuteResult {
+      output: res.output,
+      outputs: res.outputs,
+      logs: res.logs,
+      graphics,
+      messages: res.messages,
+      aborted: res.aborted,
+      expression: res.expression,
+      elapsed_ms
+    },
+    images
+  )
+}
+
+/// Per-cell results of a file run or batch, with every cell's images as
+/// content blocks in order.
+fn cell_results(runs: Vec<script::CellRun>) -> (Vec<CellResult>, Vec<Content>) {
+  let mut cells = Vec::new();
+  let mut content = Vec::new();
+  for run in runs {
+    let (result, error) = match run.result {
+      | Ok(res) => {
+        let (mut result, images) = execute_result(res, run.elapsed.as_millis() as u64);
+        // Each cell numbers its images from 1; shift them past the earlier
+        // cells' blocks.
+        for image in &mut result.graphics {
+          image.content_index += content.len();
+        }
+        content.extend(images);
+        (Some(result), None)
+      }
+      | Err(e) => (None, Some(e.to_string()))
+    };
+    cells.push(CellResult {
+      index: run.index,
+      code: run.code,
+      result,
+      error
+    });
+  }
+  (cells, content)
+}
+
+/// Like returning `Json<T>`, plus `images` as extra content blocks. Errors
+/// stay tool errors, as with `Result<Json<T>, String>`.
+fn with_images<T: Serialize>(
+  result: Result<(T, Vec<Content>), String>
+) -> Result<CallToolResult, ErrorData> {
+  let (result, images) = match result {
+    | Ok(ok) => ok,
+    | Err(e) => return Ok(CallToolResult::error(vec![Content::text(e)]))
+  };
+  let value = serde_json::to_value(result).map_err(|e| {
+    ErrorData::internal_error(format!("Failed to serialize structured content: {e}"), None)
+  })?;
+  let mut res = CallToolResult::structured(value);
+  res.content.extend(images);
+  Ok(res)
+}
+
+/// The answer to a kernel input request, as the client is asked for it.
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct InputAnswer {
+  /// Wolfram Language input for `Input[]`, or the text `InputString[]`
+  /// returns.
+  answer: String
+}
+
+rmcp::elicit_safe!(InputAnswer);
+
+/// Forward kernel input requests from an evaluation to the client as
+/// elicitation requests. The task ends with the evaluation, when the last
+/// sender is dropped.
+fn elicit_input(peer: Peer<RoleServer>) -> InputChannel {
+  let (input, requests) = InputChannel::new();
+  tokio::spawn(async move {
+    while let Ok(pending) = requests.recv_async().await {
+      let message = match pending.request.kind {
+        | InputKind::Expression => {
+          format!("The Wolfram kernel asks for an expression: {}", pending.request.prompt)
+        }
+        | InputKind::String => format!("The Wolfram kernel asks: {}", pending.request.prompt)
+      };
+      let answer = match peer.elicit::<InputAnswer>(message).await {
+        | Ok(Some(InputAnswer {
+          answer
+        })) => Ok(answer),
+        | Ok(None) => Err(anyhow!("the client sent no answer")),
+        | Err(ElicitationError::CapabilityNotSupported) => {
+          Err(anyhow!("the MCP client does not support elicitation"))
+        }
+        | Err(ElicitationError::UserDeclined) => Err(anyhow!("the user declined to answer")),
+        | Err(ElicitationError::UserCancelled) => Err(anyhow!("the user cancelled the request")),
+        | Err(e) => Err(anyhow!("elicitation failed: {e}"))
+      };
+      let _ = pending.reply.send(answer);
+    }
+  });
+  input
+}
+
+/// Forward an evaluation's `Print` output and progress reports to the client
+/// as they arrive. Progress goes out as `notifications/progress` when the
+/// request carries a progress token; `Print` lines, and progress without a
+/// token, go out as `info` log messages from the `print` and `progress`
+/// loggers. The task ends with the evaluation, when the last sender is
+/// dropped.
+fn relay_events(
+  peer: Peer<RoleServer>,
+  progress_token: Option<ProgressToken>,
+  log_level: Arc<Mutex<LoggingLevel>>
+) -> EventChannel {
+  let (events, reported) = EventChannel::new();
+  tokio::spawn(async move {
+    while let Ok(event) = reported.recv_async().await {
+      let (logger, data) = match (event, &progress_token) {
+        | (
+          EvalEvent::Progress {
+            done,
+            total,
+            message
+          },
+          Some(token)
+        ) => {
+          let _ = peer
+            .notify_progress(ProgressNotificationParam {
+              progress_token: token.clone(),
+              progress: done,
+              total,
+              message
+            })
+            .await;
+          continue;
+        }
+        | (
+          EvalEvent::Progress {
+            done,
+            total,
+            message
+          },
+          None
+        ) => {
+          ("progress", serde_json::json!({"progress": done, "total": total, "message": message}))
+        }
+        | (EvalEvent::Print(line), _) => ("print", serde_json::Value::String(line))
+      };
+      // `LoggingLevel` runs from least to most severe.
+      if (LoggingLevel::Info as u8) < (*log_level.lock().unwrap() as u8) {
+        continue;
+      }
+      let _ = peer
+        .notify_logging_message(LoggingMessageNotificationParam {
+          level: LoggingLevel::Info,
+          logger: Some(logger.to_string()),
+          data
+        })
+        .await;
+    }
+  });
+  events
+}
+
+/// Compare without an early exit, so the admin token cannot be guessed byte by
+/// byte from response times.
+fn constant_time_eq(
+  a: &[u8],
+  b: &[u8]
+) -> bool {
+  a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub async fn run_server(options: SessionOptions) -> anyhow::Result<()> {
+  let server = MathematicaServer::new(options);
+  let (sessions, client) = (server.sessions.clone(), server.client.id.clone());
 
   // Serve over stdio.
   let transport = stdio();
   let running = serve_server(server, transport).await?;
   running.waiting().await?;
 
+  // The client hung up; don't leave its kernels running.
+  sessions.close_client_sessions(&client).await;
+
+  Ok(())
+}
+
+/// Serve streamable HTTP (with SSE responses) at `http://<addr>/mcp`.
+pub async fn run_http_server(
+  options: SessionOptions,
+  addr: SocketAddr,
+  admin_token: Option<String>
+) -> anyhow::Result<()> {
+  let router = http_router(MathematicaServer::new(options).with_admin_token(admin_token));
+  let listener =
+    tokio::net::TcpListener::bind(addr).await.map_err(|e| anyhow!("failed to bind {addr}: {e}"))?;
+  tracing::info!("listening on http://{}/mcp", listener.local_addr()?);
+  axum::serve(listener, router).await?;
   Ok(())
 }
+
+/// Every MCP session gets its own server instance, and with it its own client
+/// identity for session ownership; all of them share one `SessionManager`.
+/// Requests carrying the same `Mcp-Session-Id` are the same client, over any
+/// connection. A client that starts a new MCP session is a new client: its old
+/// kernel sessions are closed when the old MCP session ends and cannot be
+/// reclaimed.
+fn http_router<B: KernelBackend>(server: MathematicaServer<B>) -> axum::Router {
+  let service = StreamableHttpService::new(
+    move || Ok(server.for_new_client()),
+    LocalSessionManager::default().into(),
+    StreamableHttpServerConfig::default()
+  );
+  axum::Router::new().nest_service("/mcp", service)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use rmcp::model::RawContent;
+
+  use super::*;
+  use crate::backend::mock::{
+    MockBackend,
+    MockReply
+  };
+
+  fn server(backend: MockBackend) -> MathematicaServer<MockBackend> {
+    MathematicaServer::with_sessions(SessionManager::with_backend(backend))
+  }
+
+  async fn create(server: &MathematicaServer<MockBackend>) -> String {
+    server.create(&server.client(), CreateSessionParams::default()).await.unwrap().session_id
+  }
+
+  /// What the `mathematica_execute_code` tool returns, minus the client
+  /// connection it would elicit input through.
+  async fn execute_code(
+    server: &MathematicaServer<MockBackend>,
+    params: ExecuteParams
+  ) -> CallToolResult {
+    with_images(server.execute(&server.client(), params, CallContext::default()).await).unwrap()
+  }
+
+  fn execute_params(
+    session_id: &str,
+    code: &str
+  ) -> ExecuteParams {
+    ExecuteParams {
+      session_id:         session_id.to_string(),
+      code:               code.to_string(),
+      timeout_seconds:    None,
+      output_format:      None,
+      include_expression: false,
+      graphics:           None
+    }
+  }
+
+  #[tokio::test]
+  async fn execute_code_returns_output_logs_and_graphics() {
+    let backend = MockBackend::new().on(
+      "Plot",
+      MockReply::output("-Graphics-").with_logs(&["plotting"]).with_graphics(
+        &[],
+        "Graphics",
+        "iVBOR"
+      )
+    );
+    let server = server(backend);
+    let id = create(&server).await;
+
+    let res = execute_code(&server, execute_params(&id, "Plot[x, {x, 0, 1}]")).await;
+    assert_eq!(res.is_error, Some(false));
+    let structured = res.structured_content.unwrap();
+    assert_eq!(structured["output"], "-Graphics-");
+    assert_eq!(structured["logs"], serde_json::json!(["plotting"]));
+    assert_eq!(
+      structured["graphics"],
+      serde_json::json!([
+        {"position": [], "head": "Graphics", "mime_type": "image/png", "content_index": 1}
+      ])
+    );
+
+    assert_eq!(res.content.len(), 2);
+    assert!(res.content[0].as_text().is_some());
+    let image = res.content[1].as_image().unwrap();
+    assert_eq!(image.data, "iVBOR");
+    assert_eq!(image.mime_type, "image/png");
+  }
+
+  #[tokio::test]
+  async fn earlier_outputs_are_retrievable_from_the_history() {
+    let backend = MockBackend::new()
+      .on("Plot", MockReply::output("-Graphics-").with_graphics(&[], "Graphics", "iVBOR"))
+      .on("boom", MockReply::failure("link trouble"));
+    let server = server(backend);
+    let id = create(&server).await;
+    execute_code(&server, execute_params(&id, "Plot[x, {x, 0, 1}]")).await;
+    execute_code(&server, execute_params(&id, "boom")).await;
+    execute_code(&server, execute_params(&id, "1+1")).await;
+
+    let history = server
+      .history(&server.client(), GetHistoryParams {
+        session_id: id.clone(),
+        from:       Some(2),
+        limit:      None
+      })
+      .await
+      .unwrap();
+    assert_eq!(history.total, 3);
+    let entries = serde_json::to_value(&history.entries).unwrap();
+    assert_eq!(entries[0]["n"], 2);
+    assert_eq!(entries[0]["error"], "link trouble");
+    assert_eq!(entries[1]["code"], "1+1");
+    assert_eq!(entries[1]["output"], "1+1");
+
+    let params = |n| {
+      GetOutputParams {
+        session_id: id.clone(),
+        n
+      }
+    };
+    let res = with_images(server.output(&server.client(), params(1)).await).unwrap();
+    let structured = res.structured_content.unwrap();
+    assert_eq!(structured["n"], 1);
+    assert_eq!(structured["code"], "Plot[x, {x, 0, 1}]");
+    assert_eq!(structured["output"], "-Graphics-");
+    assert_eq!(res.content[1].as_image().unwrap().data, "iVBOR");
+
+    let (last, _) = server.output(&server.client(), params(-1)).await.unwrap();
+    assert_eq!(last.n, 3);
+    let err = server.output(&server.client(), params(2)).await.unwrap_err();
+    assert!(err.contains("evaluation 2 failed: link trouble"), "{err}");
+    let err = server.output(&server.client(), params(4)).await.unwrap_err();
+    assert!(err.contains("no evaluation 4"), "{err}");
+
+    let other = server.for_new_client();
+    let err = other.output(&other.client(), params(1)).await.unwrap_err();
+    assert!(err.contains("not found"), "{err}");
+  }
+
+  #[tokio::test]
+  async fn sessions_export_as_notebooks_built_by_the_kernel() {
+    let path = std::env::temp_dir().join("transcript.nb");
+    let backend = MockBackend::new()
+      .on(
+        "ExportString[Notebook[",
+        MockReply::output("(* Content-type: application/vnd.wolfram.mathematica *)")
+      )
+      .on("Export[", MockReply::output(&path.display().to_string()))
+      .on("Plot", MockReply::output("-Graphics-").with_graphics(&[], "Graphics", "iVBOR"))
+      .on("hello", MockReply::output("Null").with_logs(&["hello"]));
+    let server = server(backend.clone());
+    let id = create(&server).await;
+    execute_code(&server, execute_params(&id, "Print[\"hello\"]")).await;
+    execute_code(&server, execute_params(&id, "Plot[x, {x, 0, 1}]")).await;
+
+    let params = |path: Option<&std::path::Path>| {
+      ExportSessionParams {
+        session_id: id.clone(),
+        format:     None,
+        path:       path.map(|p| p.display().to_string())
+      }
+    };
+    let (written, content) = server.export(&server.client(), params(Some(&path))).await.unwrap();
+    assert_eq!(written.format, ExportFormat::Notebook);
+    assert_eq!(written.cells, 2);
+    assert_eq!(written.path.as_deref(), Some(path.display().to_string().as_str()));
+    assert!(content.is_empty());
+    let code = backend.evaluations().pop().unwrap();
+    assert!(code.starts_with(&format!("Export[\"{}\", Notebook[{{", path.display())), "{code}");
+    assert!(code.contains("Cell[\"hello\", \"Print\"]"), "{code}");
+    assert!(code.contains("ImportByteArray[BaseDecode[\"iVBOR\"], \"PNG\"]"), "{code}");
+    assert!(!code.contains("\"-Graphics-\""), "{code}");
+
+    let res = with_images(server.export(&server.client(), params(None)).await).unwrap();
+    assert_eq!(res.structured_content.unwrap()["path"], serde_json::Value::Null);
+    let RawContent::Resource(resource) = &res.content[1].raw else {
+      panic!("expected the notebook as a resource: {:?}", res.content);
+    };
+    let ResourceContents::TextResourceContents {
+      mime_type,
+      text,
+      ..
+    } = &resource.resource
+    else {
+      panic!("expected a text resource");
+    };
+    assert_eq!(mime_type.as_deref(), Some("application/vnd.wolfram.nb"));
+    assert!(text.starts_with("(* Content-type"), "{text}");
+
+    let history = server.sessions.history(&id).await.unwrap();
+    assert_eq!(history.entries().len(), 2, "exports stay out of the history");
+
+    let evaluations = backend.evaluations().len();
+    let mut ipynb = params(None);
+    ipynb.format = Some(ExportFormat::Ipynb);
+    let (_, content) = server.export(&server.client(), ipynb).await.unwrap();
+    let RawContent::Resource(resource) = &content[0].raw else {
+      panic!("expected the notebook as a resource: {content:?}");
+    };
+    let ResourceContents::TextResourceContents {
+      uri, ..
+    } = &resource.resource
+    else {
+      panic!("expected a text resource");
+    };
+    assert!(uri.ends_with(".ipynb"), "{uri}");
+    assert_eq!(backend.evaluations().len(), evaluations, "only .nb export needs the kernel");
+
+    let params = params(Some(std::path::Path::new("notes.txt")));
+    let err = server.export(&server.client(), params).await.unwrap_err();
+    assert!(err.contains("cannot tell the export format"), "{err}");
+  }
+
+  #[tokio::test]
+  async fn submitted_jobs_are_polled_and_fetched() {
+    let backend = MockBackend::new()
+      .on("Plot", MockReply::output("-Graphics-").with_graphics(&[], "Graphics", "iVBOR"))
+      .on("Pause", MockReply::output("Null").delayed(Duration::from_secs(60)));
+    let server = server(backend);
+    let id = create(&server).await;
+    let submit = |code: &str| {
+      serde_json::from_value::<SubmitParams>(serde_json::json!({"session_id": id, "code": code}))
+        .unwrap()
+    };
+    let job = |job_id: &str| {
+      JobParams {
+        job_id: job_id.to_string()
+      }
+    };
+
+    let plot = server.submit(&server.client(), submit("Plot[x, {x, 0, 1}]")).await.unwrap().job_id;
+    let mut status = server.job_status(&server.client(), job(&plot)).unwrap();
+    for _ in 0..250 {
+      if status.state != JobState::Running {
+        break;
+      }
+      tokio::time::sleep(Duration::from_millis(20)).await;
+      status = server.job_status(&server.client(), job(&plot)).unwrap();
+    }
+    assert_eq!(status.state, JobState::Completed);
+    assert!(status.finished_at_utc.is_some());
+    let (result, images) = server.job_result(&server.client(), job(&plot)).unwrap();
+    assert_eq!(result.result.output, "-Graphics-");
+    assert_eq!(images.len(), 1);
+
+    let pause = server.submit(&server.client(), submit("Pause[600]")).await.unwrap().job_id;
+    let err = server.job_result(&server.client(), job(&pause)).unwrap_err();
+    assert!(err.contains("still running"), "{err}");
+    let status = server.job_cancel(&server.client(), job(&pause)).unwrap();
+    assert_eq!(status.state, JobState::Cancelled);
+
+    let other = server.for_new_client();
+    let err = other.job_status(&other.client(), job(&plot)).unwrap_err();
+    assert!(err.contains("not found"), "{err}");
+  }
+
+  #[tokio::test]
+  async fn batches_return_a_result_per_cell_and_stop_on_errors() {
+    let backend = MockBackend::new()
+      .on(
+        "1/0",
+        MockReply::output("ComplexInfinity").with_message(
+          "Power",
+          "infy",
+          "Infinite expression 1/0 encountered.",
+          &["1/0"]
+        )
+      )
+      .on("Plot", MockReply::output("-Graphics-").with_graphics(&[], "Graphics", "iVBOR"));
+    let server = server(backend);
+    let id = create(&server).await;
+    let params = |stop_on_error| {
+      serde_json::from_value::<ExecuteBatchParams>(serde_json::json!({
+        "session_id": id,
+        "cells": ["Plot[x, {x, 0, 1}]", "y = 2", "1/0", "Plot[y x, {x, 0, 1}]"],
+        "stop_on_error": stop_on_error
+      }))
+      .unwrap()
+    };
+
+    let (batch, images) =
+      server.batch(&server.client(), params(None), CallContext::default()).await.unwrap();
+    assert!(batch.stopped);
+    assert_eq!(batch.cells.len(), 3);
+    assert_eq!(batch.cells[1].result.as_ref().unwrap().output, "y = 2");
+    assert_eq!(batch.cells[2].result.as_ref().unwrap().messages[0].tag, "infy");
+    assert_eq!(images.len(), 1);
+
+    let (batch, images) =
+      server.batch(&server.client(), params(Some(false)), CallContext::default()).await.unwrap();
+    assert!(!batch.stopped);
+    assert_eq!(batch.cells.len(), 4);
+    assert_eq!(batch.cells[3].index, 4);
+    assert_eq!(batch.cells[3].result.as_ref().unwrap().graphics[0].content_index, 2);
+    assert_eq!(images.len(), 2);
+    assert_eq!(server.sessions.history(&id).await.unwrap().entries().len(), 7);
+  }
+
+  #[tokio::test]
+  async fn files_run_cell_by_cell_until_one_fails() {
+    let dir = std::env::temp_dir().join(format!("run-file-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let package = dir.join("derivation.wl");
+    std::fs::write(&package, "x = 1;\nPlot[x, {x, 0, 1}]\nPlot[2 x,\n  {x, 0, 1}]\nboom\nafter")
+      .unwrap();
+    let notebook = dir.join("derivation.nb");
+    std::fs::write(&notebook, "Notebook[{}]").unwrap();
+    let backend = MockBackend::new()
+      .on("ExportString[Cases[Get[", MockReply::output("[\"1 + 1\", \"Print[2]\"]"))
+      .on("Plot", MockReply::output("-Graphics-").with_graphics(&[], "Graphics", "iVBOR"))
+      .on("boom", MockReply::failure("link trouble"));
+    let server = server(backend.clone());
+    let id = create(&server).await;
+
+    let params = |path: &std::path::Path| {
+      RunFileParams {
+        session_id:      id.clone(),
+        path:            path.display().to_string(),
+        timeout_seconds: None,
+        graphics:        None
+      }
+    };
+    let (run, images) =
+      server.run(&server.client(), params(&package), CallContext::default()).await.unwrap();
+    assert_eq!(run.total_cells, 5);
+    assert!(!run.completed);
+    let codes: Vec<&str> = run.cells.iter().map(|c| c.code.as_str()).collect();
+    assert_eq!(codes, ["x = 1;", "Plot[x, {x, 0, 1}]", "Plot[2 x,\n  {x, 0, 1}]", "boom"]);
+    assert_eq!(run.cells[1].result.as_ref().unwrap().graphics[0].content_index, 1);
+    assert_eq!(run.cells[2].result.as_ref().unwrap().graphics[0].content_index, 2);
+    assert_eq!(images.len(), 2);
+    assert_eq!(run.cells[3].error.as_deref(), Some("link trouble"));
+    assert_eq!(server.sessions.history(&id).await.unwrap().entries().len(), 4);
+
+    let (run, _) =
+      server.run(&server.client(), params(&notebook), CallContext::default()).await.unwrap();
+    assert!(run.completed);
+    let codes: Vec<&str> = run.cells.iter().map(|c| c.code.as_str()).collect();
+    assert_eq!(codes, ["1 + 1", "Print[2]"]);
+
+    let err = server
+      .run(&server.client(), params(&dir.join("notes.txt")), CallContext::default())
+      .await
+      .unwrap_err();
+    assert!(err.contains("expected a .wl, .m or .nb file"), "{err}");
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn graphics_export_follows_session_defaults_and_call_overrides() {
+    let backend = MockBackend::new()
+      .on("Plot", MockReply::output("-Graphics-").with_graphics(&[], "Graphics", "PHN2Zz4="));
+    let server = server(backend);
+    let params: CreateSessionParams = serde_json::from_value(
+      serde_json::json!({"graphics": {"format": "SVG", "image_size": [400, 300]}})
+    )
+    .unwrap();
+    let id = server.create(&server.client(), params).await.unwrap().session_id;
+
+    let res = execute_code(&server, execute_params(&id, "Plot")).await;
+    let image = res.content[1].as_image().unwrap();
+    assert_eq!(image.mime_type, "image/svg+xml");
+    assert_eq!(image.data, "PHN2Zz4=");
+
+    let mut params = execute_params(&id, "Plot");
+    params.graphics = Some(GraphicsOptions {
+      format: Some(GraphicsFormat::Pdf),
+      ..GraphicsOptions::default()
+    });
+    let res = execute_code(&server, params).await;
+    assert_eq!(res.structured_content.unwrap()["graphics"][0]["mime_type"], "application/pdf");
+    let RawContent::Resource(embedded) = &res.content[1].raw else {
+      panic!("expected an embedded resource, got {:?}", res.content[1]);
+    };
+    let ResourceContents::BlobResourceContents {
+      mime_type,
+      blob,
+      ..
+    } = &embedded.resource
+    else {
+      panic!("expected a blob resource");
+    };
+    assert_eq!(mime_type.as_deref(), Some("application/pdf"));
+    assert_eq!(blob, "PHN2Zz4=");
+  }
+
+  #[tokio::test]
+  async fn execute_code_returns_every_nested_graphic_with_its_position() {
+    let plot_key = Part::Key {
+      key: "\"plot\"".to_string()
+    };
+    let backend = MockBackend::new().on(
+      "Table",
+      MockReply::output("{-Graphics-, <|\"plot\" -> -Image-|>}")
+        .with_graphics(&[Part::Index(1)], "Graphics", "AAAA")
+        .with_graphics(&[Part::Index(2), plot_key.clone()], "Image", "BBBB")
+    );
+    let server = server(backend);
+    let id = create(&server).await;
+
+    let res = execute_code(&server, execute_params(&id, "Table[...]")).await;
+    let structured = res.structured_content.unwrap();
+    assert_eq!(
+      structured["graphics"],
+      serde_json::json!([
+        {"position": [1], "head": "Graphics", "mime_type": "image/png", "content_index": 1},
+        {"position": [2, {"key": "\"plot\""}], "head": "Image", "mime_type": "image/png", "content_index": 2}
+      ])
+    );
+    let images: Vec<_> =
+      res.content[1..].iter().map(|c| c.as_image().unwrap().data.as_str()).collect();
+    assert_eq!(images, vec!["AAAA", "BBBB"]);
+  }
+
+  #[tokio::test]
+  async fn execute_code_reports_errors_as_tool_errors() {
+    let server = server(MockBackend::new());
+    let res = execute_code(&server, execute_params("not-a-session", "1")).await;
+    assert_eq!(res.is_error, Some(true));
+    assert!(res.structured_content.is_none());
+    let text = &res.content[0].as_text().unwrap().text;
+    assert!(text.contains("Invalid session ID"), "{text}");
+  }
+
+  #[tokio::test]
+  async fn execute_code_separates_messages_from_logs() {
+    let backend = MockBackend::new().on(
+      "1/0",
+      MockReply::output("ComplexInfinity").with_logs(&["printed"]).with_message(
+        "Power",
+        "infy",
+        "Infinite expression 1/0 encountered.",
+        &["1/0"]
+      )
+    );
+    let server = server(backend);
+    let id = create(&server).await;
+
+    let res = server
+      .execute(
+        &server.client(),
+        execute_params(&id, "Print[\"printed\"]; 1/0"),
+        CallContext::default()
+      )
+      .await
+      .unwrap()
+      .0;
+    assert_eq!(res.logs, vec!["printed".to_string()]);
+    assert_eq!(res.messages, vec![WolframMessage {
+      symbol:    "Power".to_string(),
+      tag:       "infy".to_string(),
+      text:      "Infinite expression 1/0 encountered.".to_string(),
+      arguments: vec!["1/0".to_string()]
+    }]);
+  }
+
+  #[tokio::test]
+  async fn execute_code_renders_requested_output_formats() {
+    let backend = MockBackend::new().on(
+      "x^2",
+      MockReply::output("x^2")
+        .with_rendering(OutputFormat::TeXForm, "x^2")
+        .with_rendering(OutputFormat::FullForm, "Power[x, 2]")
+    );
+    let server = server(backend);
+    let id = create(&server).await;
+
+    let mut params = execute_params(&id, "x^2");
+    params.output_format =
+      Some(OutputFormatSpec::Many(vec![OutputFormat::FullForm, OutputFormat::InputForm]));
+    let res = server.execute(&server.client(), params, CallContext::default()).await.unwrap().0;
+    assert_eq!(res.output, "Power[x, 2]");
+    assert_eq!(res.outputs, vec![
+      FormattedOutput {
+        format: OutputFormat::FullForm,
+        text:   "Power[x, 2]".to_string()
+      },
+      FormattedOutput {
+        format: OutputFormat::InputForm,
+        text:   "x^2".to_string()
+      },
+    ]);
+
+    let res = server
+      .execute(&server.client(), execute_params(&id, "x^2"), CallContext::default())
+      .await
+      .unwrap()
+      .0;
+    assert_eq!(res.outputs.len(), 1);
+    assert_eq!(res.outputs[0].format, OutputFormat::InputForm);
+  }
+
+  #[tokio::test]
+  async fn execute_code_returns_expression_tree_on_request() {
+    let tree = ExprTree::Rational {
+      numerator:   "1".to_string(),
+      denominator: "3".to_string()
+    };
+    let backend =
+      MockBackend::new().on("1/3", MockReply::output("1/3").with_expression(tree.clone()));
+    let server = server(backend);
+    let id = create(&server).await;
+
+    let res = server
+      .execute(&server.client(), execute_params(&id, "1/3"), CallContext::default())
+      .await
+      .unwrap()
+      .0;
+    assert_eq!(res.expression, None);
+
+    let mut params = execute_params(&id, "1/3");
+    params.include_expression = true;
+    let res = server.execute(&server.client(), params, CallContext::default()).await.unwrap().0;
+    assert_eq!(res.expression, Some(tree));
+  }
+
+  #[test]
+  fn output_format_accepts_a_single_name_or_a_list() {
+    let one: OutputFormatSpec = serde_json::from_str("\"TeXForm\"").unwrap();
+    assert_eq!(one.into_vec(), vec![OutputFormat::TeXForm]);
+
+    let many: OutputFormatSpec = serde_json::from_str("[\"InputForm\", \"LaTeX\"]").unwrap();
+    assert_eq!(many.into_vec(), vec![OutputFormat::InputForm, OutputFormat::TeXForm]);
+
+    assert!(serde_json::from_str::<OutputFormatSpec>("\"PrettyForm\"").is_err());
+  }
+
+  #[tokio::test]
+  async fn execute_code_rejects_malformed_session_ids() {
+    let server = server(MockBackend::new());
+    let err = server
+      .execute(&server.client(), execute_params("not-a-session", "1"), CallContext::default())
+      .await
+      .err()
+      .unwrap();
+    assert!(err.contains("Invalid session ID"), "{err}");
+  }
+
+  #[tokio::test]
+  async fn execute_code_aborts_on_timeout() {
+    let backend =
+      MockBackend::new().on("Pause", MockReply::output("Null").delayed(Duration::from_secs(60)));
+    let server = server(backend);
+    let id = create(&server).await;
+
+    let mut params = execute_params(&id, "Pause[100]");
+    params.timeout_seconds = Some(0);
+    let res = server.execute(&server.client(), params, CallContext::default()).await.unwrap().0;
+    assert!(res.aborted);
+    assert_eq!(res.output, "$Aborted");
+
+    let res = server
+      .execute(&server.client(), execute_params(&id, "1 + 1"), CallContext::default())
+      .await
+      .unwrap()
+      .0;
+    assert!(!res.aborted);
+    assert_eq!(res.output, "1 + 1");
+  }
+
+  #[tokio::test]
+  async fn check_syntax_locates_errors_without_evaluating() {
+    let backend = MockBackend::new()
+      .on("f[2]]", MockReply::output("11"))
+      .on("SyntaxQ", MockReply::output("-1"));
+    let server = server(backend.clone());
+    let id = create(&server).await;
+    let check = |code: &str| {
+      CheckSyntaxParams {
+        session_id: id.clone(),
+        code:       code.to_string()
+      }
+    };
+
+    let res = server.syntax_check(&server.client(), check("x = 1;\nf[2]]")).await.unwrap();
+    assert!(!res.valid);
+    let error = res.error.unwrap();
+    assert_eq!((error.offset, error.line, error.column), (11, 2, 5));
+    assert!(!error.incomplete);
+
+    let res = server.syntax_check(&server.client(), check("Plot[x, {x, 0, 1}]")).await.unwrap();
+    assert!(res.valid);
+    assert!(res.error.is_none());
+    // Neither check lands in the history.
+    assert!(server.sessions.history(&id).await.unwrap().entries().is_empty());
+    assert!(backend.evaluations().iter().all(|code| code.contains("SyntaxQ")));
+  }
+
+  #[tokio::test]
+  async fn execute_code_locates_syntax_errors_in_the_code() {
+    let backend = MockBackend::new()
+      .on("SyntaxQ", MockReply::output("11"))
+      .on("f[2]]", MockReply::invalid_syntax());
+    let server = server(backend);
+    let id = create(&server).await;
+
+    let err = server
+      .execute(&server.client(), execute_params(&id, "x = 1;\nf[2]]"), CallContext::default())
+      .await
+      .err()
+      .unwrap();
+    assert_eq!(err, "syntax error at line 2, column 5 (offset 11):\nf[2]]\n    ^");
+  }
+
+  #[tokio::test]
+  async fn cancelled_requests_abort_the_kernel_evaluation() {
+    let backend =
+      MockBackend::new().on("Pause", MockReply::output("Null").delayed(Duration::from_secs(60)));
+    let server = server(backend);
+    let id = create(&server).await;
+
+    let cancel = CancellationToken::new();
+    let canceller = cancel.clone();
+    tokio::spawn(async move {
+      tokio::time::sleep(Duration::from_millis(50)).await;
+      canceller.cancel();
+    });
+    let started = std::time::Instant::now();
+    let res = server
+      .execute(&server.client(), execute_params(&id, "Pause[600]"), CallContext {
+        cancel: Some(cancel),
+        ..CallContext::default()
+      })
+      .await
+      .unwrap()
+      .0;
+    assert!(res.aborted);
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // A cancelled batch skips the cells after the one it aborted.
+    let params = serde_json::from_value::<ExecuteBatchParams>(serde_json::json!({
+      "session_id": id, "cells": ["1", "Pause[600]", "2"], "stop_on_error": false
+    }))
+    .unwrap();
+    let cancel = CancellationToken::new();
+    let canceller = cancel.clone();
+    tokio::spawn(async move {
+      tokio::time::sleep(Duration::from_millis(50)).await;
+      canceller.cancel();
+    });
+    let (batch, _) = server
+      .batch(&server.client(), params, CallContext {
+        cancel: Some(cancel),
+        ..CallContext::default()
+      })
+      .await
+      .unwrap();
+    assert!(batch.stopped);
+    assert_eq!(batch.cells.len(), 2);
+
+    let res = server
+      .execute(&server.client(), execute_params(&id, "1 + 1"), CallContext::default())
+      .await
+      .unwrap()
+      .0;
+    assert_eq!(res.output, "1 + 1");
+  }
+
+  #[tokio::test]
+  async fn execute_code_forwards_input_requests() {
+    let backend = MockBackend::new().on("Input", MockReply::input(InputKind::Expression, "n = "));
+    let server = server(backend);
+    let id = create(&server).await;
+
+    let (input, requests) = InputChannel::new();
+    tokio::spawn(async move {
+      while let Ok(pending) = requests.recv_async().await {
+        assert_eq!(pending.request.prompt, "n = ");
+        let _ = pending.reply.send(Ok("7".to_string()));
+      }
+    });
+    let res = server
+      .execute(&server.client(), execute_params(&id, "Input[\"n = \"]"), CallContext {
+        input: Some(input),
+        ..CallContext::default()
+      })
+      .await
+      .unwrap()
+      .0;
+    assert_eq!(res.output, "7");
+
+    let err = server
+      .execute(&server.client(), execute_params(&id, "Input[\"n = \"]"), CallContext::default())
+      .await
+      .err()
+      .unwrap();
+    assert!(err.contains("asked for input"), "{err}");
+  }
+
+  #[tokio::test]
+  async fn execute_code_reports_print_output_and_progress_as_it_runs() {
+    let progress = EvalEvent::Progress {
+      done:    1.0,
+      total:   Some(2.0),
+      message: Some("fitting".to_string())
+    };
+    let backend = MockBackend::new().on(
+      "Progress",
+      MockReply::output("Null").with_logs(&["step 1"]).reporting(std::slice::from_ref(&progress))
+    );
+    let server = server(backend);
+    let id = create(&server).await;
+
+    let (events, reported) = EventChannel::new();
+    let code = "Print[\"step 1\"]; MathematicaMCP`Progress[1, 2, \"fitting\"]";
+    let res = server
+      .execute(&server.client(), execute_params(&id, code), CallContext {
+        events: Some(events),
+        ..CallContext::default()
+      })
+      .await
+      .unwrap()
+      .0;
+    assert_eq!(res.logs, vec!["step 1".to_string()]);
+    assert_eq!(reported.drain().collect::<Vec<_>>(), vec![
+      progress,
+      EvalEvent::Print("step 1".to_string())
+    ]);
+  }
+
+  /// Send one request to the HTTP router and return the MCP session id header
+  /// along with the JSON-RPC response, if there is one.
+  async fn send(
+    router: &axum::Router,
+    method: axum::http::Method,
+    headers: &[(&str, &str)],
+    message: Option<serde_json::Value>
+  ) -> (Option<String>, Option<serde_json::Value>) {
+    use axum::http::{
+      Request,
+      header
+    };
+    use tower::ServiceExt;
+
+    let mut request = Request::builder()
+      .method(method)
+      .uri("/mcp")
+      .header(header::ACCEPT, "application/json, text/event-stream")
+      .header(header::CONTENT_TYPE, "application/json");
+    for (name, value) in headers {
+      request = request.header(*name, *value);
+    }
+    let body = message.map_or_else(String::new, |m| m.to_string());
+    let response =
+      router.clone().oneshot(request.body(axum::body::Body::from(body)).unwrap()).await.unwrap();
+    assert!(response.status().is_success(), "{}", response.status());
+    let session =
+      response.headers().get("mcp-session-id").map(|id| id.to_str().unwrap().to_string());
+    let body = tokio::time::timeout(
+      Duration::from_secs(5),
+      axum::body::to_bytes(response.into_body(), usize::MAX)
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    let reply = String::from_utf8_lossy(&body)
+      .lines()
+      .filter_map(|line| line.strip_prefix("data:"))
+      .filter_map(|data| serde_json::from_str::<serde_json::Value>(data.trim()).ok())
+      .find(|reply| reply.get("id").is_some());
+    (session, reply)
+  }
+
+  /// Open a new MCP session over HTTP.
+  async fn connect(router: &axum::Router) -> String {
+    let (session, reply) = send(
+      router,
+      axum::http::Method::POST,
+      &[],
+      Some(serde_json::json!({
+        "jsonrpc": "2.0", "id": 1, "method": "initialize",
+        "params": {
+          "protocolVersion": "2025-06-18",
+          "capabilities": {},
+          "clientInfo": {"name": "test", "version": "0"}
+        }
+      }))
+    )
+    .await;
+    assert!(reply.unwrap().get("result").is_some());
+    let session = session.unwrap();
+    send(
+      router,
+      axum::http::Method::POST,
+      &[("mcp-session-id", &session)],
+      Some(serde_json::json!({"jsonrpc": "2.0", "method": "notifications/initialized"}))
+    )
+    .await;
+    session
+  }
+
+  async fn call_tool(
+    router: &axum::Router,
+    headers: &[(&str, &str)],
+    name: &str,
+    arguments: serde_json::Value
+  ) -> serde_json::Value {
+    let (_, reply) = send(
+      router,
+      axum::http::Method::POST,
+      headers,
+      Some(serde_json::json!({
+        "jsonrpc": "2.0", "id": 2, "method": "tools/call",
+        "params": {"name": name, "arguments": arguments}
+      }))
+    )
+    .await;
+    reply.unwrap()["result"].clone()
+  }
+
+  #[tokio::test]
+  async fn http_sessions_belong_to_their_mcp_session() {
+    let sessions =
+      SessionManager::with_backend(MockBackend::new().on("1+1", MockReply::output("2")));
+    let router = http_router(
+      MathematicaServer::with_sessions(sessions.clone())
+        .with_admin_token(Some("hunter2".to_string()))
+    );
+    let eval = |id: &str| serde_json::json!({"session_id": id, "code": "1+1"});
+
+    let first = connect(&router).await;
+    let created = call_tool(
+      &router,
+      &[("mcp-session-id", &first)],
+      "mathematica_create_session",
+      serde_json::json!({})
+    )
+    .await;
+    let id = created["structuredContent"]["session_id"].as_str().unwrap().to_string();
+
+    // Every request is a fresh HTTP connection; the MCP session id is what
+    // identifies the client across them.
+    let res =
+      call_tool(&router, &[("mcp-session-id", &first)], "mathematica_execute_code", eval(&id))
+        .await;
+    assert_eq!(res["structuredContent"]["output"], "2");
+
+    let second = connect(&router).await;
+    let res =
+      call_tool(&router, &[("mcp-session-id", &second)], "mathematica_execute_code", eval(&id))
+        .await;
+    assert_eq!(res["isError"], true);
+    let listed = call_tool(
+      &router,
+      &[("mcp-session-id", &second)],
+      "mathematica_list_sessions",
+      serde_json::json!({})
+    )
+    .await;
+    assert_eq!(listed["structuredContent"]["sessions"], serde_json::json!([]));
+
+    let admin = [("mcp-session-id", second.as_str()), ("authorization", "Bearer hunter2")];
+    let res = call_tool(&router, &admin, "mathematica_execute_code", eval(&id)).await;
+    assert_eq!(res["structuredContent"]["output"], "2");
+
+    // Ending the first MCP session closes the kernel sessions it owns.
+    send(&router, axum::http::Method::DELETE, &[("mcp-session-id", &first)], None).await;
+    for _ in 0..200 {
+      if sessions.list_sessions().await.is_empty() {
+        return;
+      }
+      tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    panic!("session of a disconnected client was not closed");
+  }
+
+  #[tokio::test]
+  async fn clients_cannot_use_each_others_sessions() {
+    let sessions = SessionManager::with_backend(MockBackend::new());
+    let alice = MathematicaServer::with_sessions(sessions.clone());
+    let bob = alice.for_new_client();
+    let id = create(&alice).await;
+
+    let err = bob
+      .execute(&bob.client(), execute_params(&id, "1"), CallContext::default())
+      .await
+      .err()
+      .unwrap();
+    assert!(err.contains("not found"), "{err}");
+    let err = bob
+      .close(&bob.client(), CloseSessionParams {
+        session_id: id.clone()
+      })
+      .await
+      .err()
+      .unwrap();
+    assert!(err.contains("not found"), "{err}");
+    assert!(bob.sessions.list_sessions_for(&bob.client()).await.is_empty());
+    assert_eq!(
+      alice
+        .execute(&alice.client(), execute_params(&id, "1"), CallContext::default())
+        .await
+        .unwrap()
+        .0
+        .output,
+      "1"
+    );
+
+    // Alice disconnects.
+    drop(alice);
+    for _ in 0..200 {
+      if sessions.list_sessions().await.is_empty() {
+        return;
+      }
+      tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    panic!("session of a disconnected client was not closed");
+  }
+
+  #[tokio::test]
+  async fn list_and_close_sessions() {
+    let server = server(MockBackend::new());
+    let a = create(&server).await;
+    let b = create(&server).await;
+
+    let listed = server.sessions.list_sessions_for(&server.client()).await;
+    assert_eq!(listed.len(), 2);
+
+    let closed = server
+      .close(&server.client(), CloseSessionParams {
+        session_id: a.clone()
+      })
+      .await
+      .unwrap();
+    assert!(closed.closed);
+    assert_eq!(closed.session_id, a);
+
+    let listed = server.sessions.list_sessions_for(&server.client()).await;
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].session_id, b);
+  }
+
+  #[tokio::test]
+  async fn get_finance_builds_and_evaluates_code() {
+    let backend = MockBackend::new().on("FinancialData", MockReply::output("187.5"));
+    let server = server(backend.clone());
+    let id = create(&server).await;
+
+    let res = server
+      .finance(
+        &server.client(),
+        FinanceParams {
+          session_id:      id,
+          symbol:          "AAPL".to_string(),
+          property:        Some("Close".to_string()),
+          start_date:      None,
+          end_date:        None,
+          interval:        None,
+          timeout_seconds: None
+        },
+        CallContext::default()
+      )
+      .await
+      .unwrap()
+      .0;
+    assert_eq!(res.wolfram_code, "FinancialData[\"AAPL\", \"Close\"]");
+    assert_eq!(res.output, "187.5");
+    assert_eq!(backend.evaluations(), vec![res.wolfram_code]);
+  }
+
+  #[tokio::test]
+  async fn create_session_surfaces_launch_errors() {
+    let server = server(MockBackend::new().fail_launch("kernel not found"));
+    let err = server.create(&server.client(), CreateSessionParams::default()).await.err().unwrap();
+    assert!(err.contains("kernel not found"), "{err}");
+  }
+}
@@ -0,0 +1,217 @@
+//! Pool of pre-launched, idle kernels.
+//!
+//! Launching a kernel takes seconds, so `SessionManager` keeps up to
+//! `min_idle` kernels warm and hands one out on `create_session` instead of
+//! launching on demand. The pool is refilled in the background whenever a
+//! kernel is taken or a session is closed.
+//!
+//! `max_kernels` caps how many kernels (active sessions plus idle and
+//! launching pool kernels) the pool is willing to pre-launch, so warming never
+//! eats the license seats that sessions need. Configuration clamps it to
+//! `session.max_sessions`, so idle kernels never push the kernel count past
+//! the session cap. Sessions created while the pool is empty still launch on
+//! demand.
+
+use std::collections::VecDeque;
+use std::sync::{
+  Arc,
+  Mutex
+};
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::backend::KernelBackend;
+use crate::session::{
+  Worker,
+  spawn_worker
+};
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+  /// Idle kernels to keep warm. `0` disables the pool.
+  pub min_idle:    usize,
+  /// Upper bound on active sessions plus pooled kernels before the pool stops
+  /// pre-launching. Never more than `session.max_sessions`.
+  pub max_kernels: usize
+}
+
+impl Default for PoolConfig {
+  fn default() -> Self {
+    Self {
+      min_idle:    0,
+      max_kernels: 4
+    }
+  }
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct PoolStatus {
+  pub idle:            usize,
+  pub launching:       usize,
+  pub active_sessions: usize,
+  pub min_idle:        usize,
+  pub max_kernels:     usize
+}
+
+#[derive(Default)]
+struct PoolState {
+  idle:      VecDeque<Worker>,
+  launching: usize
+}
+
+#[derive(Clone)]
+pub struct KernelPool {
+  config: PoolConfig,
+  state:  Arc<Mutex<PoolState>>
+}
+
+impl KernelPool {
+  pub fn new(config: PoolConfig) -> Self {
+    Self {
+      config,
+      state: Arc::new(Mutex::new(PoolState::default()))
+    }
+  }
+
+  pub(crate) fn take(&self) -> Option<Worker> {
+    self.state.lock().unwrap().idle.pop_front()
+  }
+
+  /// Launch enough kernels in the background to get back to `min_idle`,
+  /// without pushing the total past `max_kernels`.
+  pub fn refill<B: KernelBackend>(
+    &self,
+    backend: &B,
+    active_sessions: usize
+  ) {
+    let to_launch = {
+      let mut state = self.state.lock().unwrap();
+      let pooled = state.idle.len() + state.launching;
+      let wanted = self.config.min_idle.saturating_sub(pooled);
+      let room = self.config.max_kernels.saturating_sub(active_sessions + pooled);
+      let n = wanted.min(room);
+      state.launching += n;
+      n
+    };
+
+    for _ in 0..to_launch {
+      let state = self.state.clone();
+      let backend = backend.clone();
+      tokio::spawn(async move {
+        let res = spawn_worker(backend).await;
+        let mut state = state.lock().unwrap();
+        state.launching -= 1;
+        match res {
+          | Ok(worker) => {
+            tracing::debug!("pre-warmed kernel ready");
+            state.idle.push_back(worker);
+          }
+          | Err(e) => tracing::warn!("failed to pre-warm kernel: {e}")
+        }
+      });
+    }
+  }
+
+  pub fn status(
+    &self,
+    active_sessions: usize
+  ) -> PoolStatus {
+    let state = self.state.lock().unwrap();
+    PoolStatus {
+      idle: state.idle.len(),
+      launching: state.launching,
+      active_sessions,
+      min_idle: self.config.min_idle,
+      max_kernels: self.config.max_kernels
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::backend::mock::MockBackend;
+  use crate::session::{
+    SessionManager,
+    SessionOptions
+  };
+  use crate::wolfram::EvalOptions;
+
+  fn manager(
+    backend: MockBackend,
+    min_idle: usize,
+    max_kernels: usize
+  ) -> SessionManager<MockBackend> {
+    SessionManager::with_options(backend, SessionOptions {
+      pool: PoolConfig {
+        min_idle,
+        max_kernels
+      },
+      ..SessionOptions::default()
+    })
+  }
+
+  /// Wait for background launches to settle and return the final status.
+  async fn settled(manager: &SessionManager<MockBackend>) -> PoolStatus {
+    for _ in 0..200 {
+      let status = manager.pool_status().await;
+      if status.launching == 0 {
+        return status;
+      }
+      tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    panic!("pool never settled");
+  }
+
+  #[tokio::test]
+  async fn prewarms_and_refills_after_handing_out_a_kernel() {
+    let backend = MockBackend::new();
+    let manager = manager(backend.clone(), 2, 4);
+    assert_eq!(settled(&manager).await.idle, 2);
+    assert_eq!(backend.launches(), 2);
+
+    let id = manager.create_session().await.unwrap();
+    let status = settled(&manager).await;
+    assert_eq!(status.active_sessions, 1);
+    assert_eq!(status.idle, 2);
+    assert_eq!(backend.launches(), 3);
+
+    let res =
+      manager.eval(&id, "1+1", &EvalOptions::default(), Duration::from_secs(5)).await.unwrap();
+    assert_eq!(res.output, "1+1");
+  }
+
+  #[tokio::test]
+  async fn never_prelaunches_past_max_kernels() {
+    let backend = MockBackend::new();
+    let manager = manager(backend.clone(), 2, 3);
+    settled(&manager).await;
+
+    let a = manager.create_session().await.unwrap();
+    let _b = manager.create_session().await.unwrap();
+    let status = settled(&manager).await;
+    assert_eq!(status.active_sessions, 2);
+    assert_eq!(status.idle, 1);
+
+    manager.close_session(&a).await.unwrap();
+    let status = settled(&manager).await;
+    assert_eq!(status.active_sessions, 1);
+    assert_eq!(status.idle, 2);
+  }
+
+  #[tokio::test]
+  async fn disabled_pool_launches_on_demand() {
+    let backend = MockBackend::new();
+    let manager = manager(backend.clone(), 0, 4);
+    assert_eq!(settled(&manager).await.idle, 0);
+    assert_eq!(backend.launches(), 0);
+
+    manager.create_session().await.unwrap();
+    assert_eq!(settled(&manager).await.idle, 0);
+    assert_eq!(backend.launches(), 1);
+  }
+}
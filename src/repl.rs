@@ -1,30 +1,56 @@
 use std::fs;
 use std::io::ErrorKind;
-use std::path::PathBuf;
+use std::path::Path;
 
 use rustyline::DefaultEditor;
 use rustyline::error::ReadlineError;
 
-use crate::session::SessionManager;
-use crate::wolfram;
+use crate::export::{
+  self,
+  ExportFormat
+};
+use crate::session::{
+  SessionManager,
+  SessionOptions
+};
+use crate::wolfram::{
+  self,
+  EvalEvent,
+  EvalOptions,
+  EvalResult,
+  EventChannel,
+  InputChannel
+};
+use crate::{
+  script,
+  syntax
+};
 
-pub async fn run_repl() -> anyhow::Result<()> {
-  let sessions = SessionManager::new();
+pub async fn run_repl(
+  options: SessionOptions,
+  history_path: &Path
+) -> anyhow::Result<()> {
+  let sessions = SessionManager::new(options);
   let mut active: Option<String> = None;
 
-  let history_dir = PathBuf::from(".cache");
-  fs::create_dir_all(&history_dir)
-    .map_err(|e| anyhow::anyhow!("failed to create history directory: {e:?}"))?;
-  let history_path = history_dir.join("mathematica_repl_history.txt");
+  if let Some(history_dir) = history_path.parent().filter(|d| !d.as_os_str().is_empty()) {
+    fs::create_dir_all(history_dir)
+      .map_err(|e| anyhow::anyhow!("failed to create history directory: {e:?}"))?;
+  }
   eprintln!("mathematica-mcp-server repl");
   eprintln!("Commands:");
   eprintln!("  mathematica_create_session");
   eprintln!("  mathematica_list_sessions");
+  eprintln!("  mathematica_pool_status");
   eprintln!("  mathematica_time");
   eprintln!("  mathematica_execute_code <Wolfram Language code...>");
+  eprintln!("  mathematica_check_syntax <Wolfram Language code...>");
   eprintln!(
     "  mathematica_get_finance <SYMBOL> [PROPERTY] [START YYYY-MM-DD] [END YYYY-MM-DD] [INTERVAL]"
   );
+  eprintln!("  mathematica_get_history [SESSION_ID]");
+  eprintln!("  mathematica_get_output <N> [SESSION_ID]   (negative N counts back from the last)");
+  eprintln!("  mathematica_export_session <PATH.nb|.ipynb|.md> [SESSION_ID]");
   eprintln!("  mathematica_close_session [SESSION_ID]");
   eprintln!("  exit | quit");
   eprintln!();
@@ -32,7 +58,7 @@ pub async fn run_repl() -> anyhow::Result<()> {
   let mut rl =
     DefaultEditor::new().map_err(|e| anyhow::anyhow!("Failed to create readline editor: {}", e))?;
 
-  match rl.load_history(&history_path) {
+  match rl.load_history(history_path) {
     | Ok(_) => {}
     | Err(ReadlineError::Io(io_err)) if io_err.kind() == ErrorKind::NotFound => {}
     | Err(err) => return Err(anyhow::anyhow!("failed to load history: {err:?}"))
@@ -67,6 +93,12 @@ pub async fn run_repl() -> anyhow::Result<()> {
           continue;
         }
 
+        if line == "mathematica_pool_status" {
+          let status = sessions.pool_status().await;
+          eprintln!("{}", serde_json::to_string_pretty(&status)?);
+          continue;
+        }
+
         if line == "mathematica_time" {
           let now = chrono::Local::now().to_rfc3339();
           let utc = chrono::Utc::now().to_rfc3339();
@@ -79,13 +111,106 @@ pub async fn run_repl() -> anyhow::Result<()> {
             eprintln!("ERR no active session. Run mathematica_create_session first.");
             continue;
           };
-          let res = sessions.eval(id, rest, std::time::Duration::from_secs(60)).await?;
-          for log in res.logs {
-            eprintln!("LOG: {log}");
+          let options = EvalOptions {
+            input: Some(terminal_input()),
+            events: Some(terminal_progress()),
+            ..EvalOptions::default()
+          };
+          match sessions.eval(id, rest, &options, sessions.eval_timeout()).await {
+            | Ok(res) => print_result(&res),
+            | Err(e) => eprintln!("ERR {e}")
           }
-          eprintln!("{}", res.output);
-          if let Some(g) = res.graphics {
-            eprintln!("GRAPHICS: <Base64 data ({} bytes)>", g.len());
+          continue;
+        }
+
+        if let Some(rest) = line.strip_prefix("mathematica_check_syntax ") {
+          let Some(id) = active.as_deref() else {
+            eprintln!("ERR no active session. Run mathematica_create_session first.");
+            continue;
+          };
+          match syntax::check(&sessions, id, rest).await? {
+            | None => eprintln!("OK syntax is valid"),
+            | Some(error) => {
+              let kind = if error.incomplete {
+                "incomplete input"
+              } else {
+                "syntax error"
+              };
+              eprintln!("ERR {kind} at line {}, column {}:", error.line, error.column);
+              eprintln!("{}", error.snippet);
+            }
+          }
+          continue;
+        }
+
+        if line.starts_with("mathematica_get_history") {
+          // mathematica_get_history [SESSION_ID]
+          let Some(id) = line.split_whitespace().nth(1).or(active.as_deref()) else {
+            eprintln!("ERR no active session and no id provided.");
+            continue;
+          };
+          let history = match sessions.history(id).await {
+            | Ok(history) => history,
+            | Err(e) => {
+              eprintln!("ERR {e}");
+              continue;
+            }
+          };
+          for entry in history.entries() {
+            eprintln!("In[{}] ({} ms): {}", entry.n, entry.elapsed.as_millis(), entry.code);
+            match &entry.result {
+              | Ok(res) => eprintln!("Out[{}] = {}", entry.n, res.output),
+              | Err(e) => eprintln!("ERR[{}]: {e}", entry.n)
+            }
+          }
+          continue;
+        }
+
+        if let Some(rest) = line.strip_prefix("mathematica_get_output ") {
+          // mathematica_get_output <N> [SESSION_ID]
+          let parts: Vec<&str> = rest.split_whitespace().collect();
+          let Some(n) = parts.first().and_then(|n| n.parse::<i64>().ok()) else {
+            eprintln!("ERR usage: mathematica_get_output <N> [SESSION_ID]");
+            continue;
+          };
+          let Some(id) = parts.get(1).copied().or(active.as_deref()) else {
+            eprintln!("ERR no active session and no id provided.");
+            continue;
+          };
+          match sessions.history(id).await.and_then(|history| history.get(n)) {
+            | Ok(entry) => {
+              eprintln!("In[{}]: {}", entry.n, entry.code);
+              match &entry.result {
+                | Ok(res) => print_result(res),
+                | Err(e) => eprintln!("ERR evaluation failed: {e}")
+              }
+            }
+            | Err(e) => eprintln!("ERR {e}")
+          }
+          continue;
+        }
+
+        if let Some(rest) = line.strip_prefix("mathematica_export_session ") {
+          // mathematica_export_session <PATH> [SESSION_ID]
+          let parts: Vec<&str> = rest.split_whitespace().collect();
+          let Some(path) = parts.first().map(Path::new) else {
+            eprintln!("ERR usage: mathematica_export_session <PATH.nb|.ipynb|.md> [SESSION_ID]");
+            continue;
+          };
+          let Some(id) = parts.get(1).copied().or(active.as_deref()) else {
+            eprintln!("ERR no active session and no id provided.");
+            continue;
+          };
+          let exported = match ExportFormat::from_path(path) {
+            | Ok(format) => export::export_session(&sessions, id, format, Some(path)).await,
+            | Err(e) => Err(e)
+          };
+          match exported {
+            | Ok(exported) => {
+              let path = exported.path.unwrap_or_default();
+              eprintln!("OK exported {} evaluations to {}", exported.cells, path.display());
+            }
+            | Err(e) => eprintln!("ERR {e}")
           }
           continue;
         }
@@ -112,7 +237,8 @@ pub async fn run_repl() -> anyhow::Result<()> {
             match wolfram::build_financial_data_code(symbol, property, start, end, interval) {
               | Ok(c) => c,
               | Err(e) if e.to_string().contains("invalid date") => {
-                // Heuristic: if second argument looks like a date, likely the symbol is missing
+                // Heuristic: if second argument looks like a date, likely the
+                // symbol is missing
                 let mut warn = String::new();
                 if parts.get(1).is_some_and(|s| s.chars().all(|c| c.is_ascii_digit() || c == '-')) {
                   warn.push_str("Stock symbol missing or first argument not a ticker. ");
@@ -129,20 +255,37 @@ pub async fn run_repl() -> anyhow::Result<()> {
                 continue;
               }
               | Err(e) => {
-                if let Err(save_err) = rl.save_history(&history_path) {
+                if let Err(save_err) = rl.save_history(history_path) {
                   eprintln!("WARN: failed to save REPL history: {save_err:?}");
                 }
                 return Err(e);
               }
             };
-          let res = sessions.eval(id, &code, std::time::Duration::from_secs(60)).await?;
           eprintln!("WL: {code}");
+          let res = match sessions
+            .eval(id, &code, &EvalOptions::default(), sessions.eval_timeout())
+            .await
+          {
+            | Ok(res) => res,
+            | Err(e) => {
+              eprintln!("ERR {e}");
+              continue;
+            }
+          };
           for log in res.logs {
             eprintln!("LOG: {log}");
           }
+          for msg in res.messages {
+            eprintln!("MSG: {}::{}: {}", msg.symbol, msg.tag, msg.text);
+          }
           eprintln!("{}", res.output);
-          if let Some(g) = res.graphics {
-            eprintln!("GRAPHICS: <Base64 data ({} bytes)>", g.len());
+          for g in res.graphics {
+            let position = g.position.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ");
+            eprintln!(
+              "GRAPHICS {} at {{{position}}}: <Base64 data ({} bytes)>",
+              g.head,
+              g.data.len()
+            );
           }
           continue;
         }
@@ -186,9 +329,99 @@ pub async fn run_repl() -> anyhow::Result<()> {
     }
   }
 
-  if let Err(save_err) = rl.save_history(&history_path) {
+  if let Err(save_err) = rl.save_history(history_path) {
     eprintln!("WARN: failed to save REPL history: {save_err:?}");
   }
 
   Ok(())
 }
+
+/// Run a file cell by cell in a fresh session, printing each cell's result.
+/// Fails if a cell failed or was aborted.
+pub async fn run_file(
+  options: SessionOptions,
+  path: &Path
+) -> anyhow::Result<()> {
+  let sessions = SessionManager::new(options);
+  let id = sessions.create_session().await?;
+  let options = EvalOptions {
+    input: Some(terminal_input()),
+    events: Some(terminal_progress()),
+    ..EvalOptions::default()
+  };
+  let run = script::run_file(&sessions, &id, path, &options, sessions.eval_timeout(), None).await;
+  let _ = sessions.close_session(&id).await;
+  let run = run?;
+  for cell in &run.cells {
+    eprintln!("In[{}] ({} ms): {}", cell.index, cell.elapsed.as_millis(), cell.code);
+    match &cell.result {
+      | Ok(res) => print_result(res),
+      | Err(e) => eprintln!("ERR {e}")
+    }
+  }
+  if let Some(cell) = run.cells.last().filter(|cell| cell.failed()) {
+    anyhow::bail!("stopped at cell {} of {} in {}", cell.index, run.total, path.display());
+  }
+  Ok(())
+}
+
+fn print_result(res: &EvalResult) {
+  for log in &res.logs {
+    eprintln!("LOG: {log}");
+  }
+  for msg in &res.messages {
+    eprintln!("MSG: {}::{}: {}", msg.symbol, msg.tag, msg.text);
+  }
+  if res.aborted {
+    eprintln!("ABORTED: evaluation timed out or was cancelled");
+  }
+  eprintln!("{}", res.output);
+  for g in &res.graphics {
+    let position = g.position.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ");
+    eprintln!("GRAPHICS {} at {{{position}}}: <Base64 data ({} bytes)>", g.head, g.data.len());
+  }
+}
+
+/// Show `MathematicaMCP`Progress` reports as they arrive. `Print` output is
+/// left for [`print_result`].
+fn terminal_progress() -> EventChannel {
+  let (events, reported) = EventChannel::new();
+  tokio::spawn(async move {
+    while let Ok(event) = reported.recv_async().await {
+      if let EvalEvent::Progress {
+        done,
+        total,
+        message
+      } = event
+      {
+        let total = total.map(|total| format!("/{total}")).unwrap_or_default();
+        eprintln!("PROGRESS: {done}{total} {}", message.unwrap_or_default());
+      }
+    }
+  });
+  events
+}
+
+/// Answer `Input[]` and `InputString[]` with a prompt on the terminal.
+fn terminal_input() -> InputChannel {
+  let (input, requests) = InputChannel::new();
+  tokio::spawn(async move {
+    while let Ok(pending) = requests.recv_async().await {
+      let prompt = pending.request.prompt.clone();
+      let answer = tokio::task::spawn_blocking(move || {
+        DefaultEditor::new().and_then(|mut rl| rl.readline(&prompt))
+      })
+      .await;
+      let answer = match answer {
+        | Ok(Ok(line)) => Ok(line),
+        | Ok(Err(ReadlineError::Interrupted | ReadlineError::Eof)) => {
+          Err(anyhow::anyhow!("input cancelled"))
+        }
+        | Ok(Err(err)) => Err(anyhow::anyhow!("failed to read input: {err:?}")),
+        | Err(err) => Err(anyhow::anyhow!("input prompt failed: {err:?}"))
+      };
+      let _ = pending.reply.send(answer);
+    }
+  });
+  input
+}
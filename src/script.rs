@@ -0,0 +1,281 @@
+//! Running Wolfram Language code cell by cell, from files or batches.
+//!
+//! A `.wl` or `.m` package is split into its top-level expressions the way
+//! `Get` reads it: a newline ends an expression once the expression is
+//! complete. A `.nb` notebook contributes its input cells, converted to code
+//! by the session's kernel. Each piece is then evaluated through
+//! [`SessionManager::eval`], so it lands in the session's history like any
+//! other evaluation.
+
+use std::path::Path;
+use std::time::{
+  Duration,
+  Instant
+};
+
+use anyhow::{
+  Context,
+  anyhow,
+  bail
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::backend::KernelBackend;
+use crate::session::SessionManager;
+use crate::wolfram::{
+  EvalOptions,
+  EvalResult,
+  OutputFormat,
+  wl_string
+};
+
+/// One evaluated cell.
+#[derive(Debug)]
+pub struct CellRun {
+  /// Position in the file or batch, from 1.
+  pub index:   usize,
+  pub code:    String,
+  pub elapsed: Duration,
+  pub result:  anyhow::Result<EvalResult>
+}
+
+impl CellRun {
+  /// The cell failed or was aborted.
+  pub fn failed(&self) -> bool {
+    self.result.as_ref().map_or(true, |res| res.aborted)
+  }
+
+  /// The cell failed, was aborted, or issued kernel messages.
+  pub fn errored(&self) -> bool {
+    self.result.as_ref().map_or(true, |res| res.aborted || !res.messages.is_empty())
+  }
+}
+
+/// What a file run produced.
+#[derive(Debug)]
+pub struct FileRun {
+  /// Cells in the file.
+  pub total: usize,
+  /// The cells that ran, in order; fewer than `total` when one stopped the
+  /// run.
+  pub cells: Vec<CellRun>
+}
+
+/// Evaluate each top-level expression or input cell of `path` in the session,
+/// in order, each with `timeout`. Stops at the first cell that fails or is
+/// aborted, since later cells usually depend on it.
+pub async fn run_file<B: KernelBackend>(
+  sessions: &SessionManager<B>,
+  session_id: &str,
+  path: &Path,
+  options: &EvalOptions,
+  timeout: Duration,
+  cancel: Option<&CancellationToken>
+) -> anyhow::Result<FileRun> {
+  let pieces = load(sessions, session_id, path).await?;
+  let cells =
+    run_cells(sessions, session_id, &pieces, options, timeout, cancel, CellRun::failed).await;
+  Ok(FileRun {
+    total: pieces.len(),
+    cells
+  })
+}
+
+/// Evaluate `cells` in the session in order, each with `timeout`, until one
+/// for which `stop` holds; that cell is the last one returned. Cancelling
+/// `cancel` aborts the running cell, which then counts as failed, and skips
+/// the rest.
+pub async fn run_cells<B: KernelBackend>(
+  sessions: &SessionManager<B>,
+  session_id: &str,
+  cells: &[String],
+  options: &EvalOptions,
+  timeout: Duration,
+  cancel: Option<&CancellationToken>,
+  stop: impl Fn(&CellRun) -> bool
+) -> Vec<CellRun> {
+  let mut runs = Vec::new();
+  for (i, code) in cells.iter().enumerate() {
+    if cancel.is_some_and(CancellationToken::is_cancelled) {
+      break;
+    }
+    let started = Instant::now();
+    let result = sessions.eval_cancellable(session_id, code, options, timeout, cancel).await;
+    let run = CellRun {
+      index: i + 1,
+      code: code.clone(),
+      elapsed: started.elapsed(),
+      result
+    };
+    let stopped = stop(&run);
+    runs.push(run);
+    if stopped {
+      break;
+    }
+  }
+  runs
+}
+
+/// The code to evaluate from `path`, one entry per cell.
+async fn load<B: KernelBackend>(
+  sessions: &SessionManager<B>,
+  session_id: &str,
+  path: &Path
+) -> anyhow::Result<Vec<String>> {
+  match path.extension().and_then(|e| e.to_str()) {
+    | Some("wl" | "m") => {
+      let source = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+      Ok(split_expressions(&source))
+    }
+    | Some("nb") => notebook_inputs(sessions, session_id, path).await,
+    | _ => bail!("cannot run {}; expected a .wl, .m or .nb file", path.display())
+  }
+}
+
+/// The input cells of a notebook as InputForm code. The kernel reads the
+/// notebook and turns each cell's boxes into an expression without
+/// evaluating it; cells holding plain text are taken as they are.
+async fn notebook_inputs<B: KernelBackend>(
+  sessions: &SessionManager<B>,
+  session_id: &str,
+  path: &Path
+) -> anyhow::Result<Vec<String>> {
+  let path = std::path::absolute(path)?;
+  if !path.is_file() {
+    bail!("no such notebook: {}", path.display());
+  }
+  let code = format!(
+    "ExportString[Cases[Get[\"{}\"], Cell[content_, \"Input\" | \"Code\", ___] :> \
+     Replace[content, {{s_String :> s, boxes_ :> ToExpression[boxes, StandardForm, Function[e, \
+     ToString[Unevaluated[e], InputForm], HoldAllComplete]]}}], Infinity], \"JSON\"]",
+    wl_string(&path.to_string_lossy())
+  );
+  // OutputForm hands back the JSON without quotes or escapes.
+  let options = EvalOptions {
+    output_formats: vec![OutputFormat::OutputForm],
+    ..EvalOptions::default()
+  };
+  let res = sessions.eval_unrecorded(session_id, &code, &options, sessions.eval_timeout()).await?;
+  serde_json::from_str(&res.output).map_err(|_| {
+    let messages: Vec<String> = res.messages.iter().map(|m| m.text.clone()).collect();
+    anyhow!("the kernel could not read {}: {}", path.display(), messages.join("; "))
+  })
+}
+
+/// Split package source into its top-level expressions. Brackets, strings
+/// and comments are tracked so only newlines at the top level count, and an
+/// expression that ends in an infix operator continues on the next line.
+/// Pieces holding nothing but comments are dropped.
+pub fn split_expressions(source: &str) -> Vec<String> {
+  let mut pieces = Vec::new();
+  let mut start = 0;
+  let mut depth = 0i32;
+  // The last two characters of code seen in the current piece.
+  let mut tail = String::new();
+  let mut chars = source.char_indices().peekable();
+  while let Some((i, c)) = chars.next() {
+    let mut code = c;
+    match c {
+      | '"' => {
+        while let Some((_, c)) = chars.next() {
+          match c {
+            | '\\' => {
+              chars.next();
+            }
+            | '"' => break,
+            | _ => {}
+          }
+        }
+      }
+      | '(' if chars.peek().is_some_and(|&(_, c)| c == '*') => {
+        chars.next();
+        let mut nesting = 1;
+        let mut prev = ' ';
+        for (_, c) in chars.by_ref() {
+          match (prev, c) {
+            | ('(', '*') => nesting += 1,
+            | ('*', ')') => nesting -= 1,
+            | _ => {}
+          }
+          if nesting == 0 {
+            break;
+          }
+          // `(*)` must not close the comment it opens.
+          prev = if prev == '(' && c == '*' {
+            ' '
+          } else {
+            c
+          };
+        }
+        continue;
+      }
+      | '<' if chars.peek().is_some_and(|&(_, c)| c == '|') => {
+        chars.next();
+        depth += 1;
+        code = '|';
+      }
+      | '|' if chars.peek().is_some_and(|&(_, c)| c == '>') => {
+        chars.next();
+        depth -= 1;
+        code = ']';
+      }
+      | '(' | '[' | '{' => depth += 1,
+      | ')' | ']' | '}' => depth -= 1,
+      | '\n' if depth <= 0 && !tail.is_empty() && complete(&tail) => {
+        pieces.push(source[start..i].trim().to_string());
+        start = i + 1;
+        tail.clear();
+        continue;
+      }
+      | _ => {}
+    }
+    if !code.is_whitespace() {
+      if tail.chars().count() == 2 {
+        tail.remove(0);
+      }
+      tail.push(code);
+    }
+  }
+  if !tail.is_empty() {
+    pieces.push(source[start..].trim().to_string());
+  }
+  pieces
+}
+
+/// Whether code ending in `tail` is a complete expression rather than one
+/// waiting for an operand on the next line.
+fn complete(tail: &str) -> bool {
+  if tail.ends_with("++") || tail.ends_with("--") {
+    return true;
+  }
+  if tail.ends_with("&&") || tail.ends_with("/.") {
+    return false;
+  }
+  !tail.ends_with(['+', '-', '*', '/', '^', '=', ',', '@', '<', '>', '|', '~', ':', '?', '\\'])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn packages_split_at_complete_top_level_expressions() {
+    let source = "(* setup *)\nf[x_] :=\n  x^2 +\n  1\ng = {1,\n 2}; (* trailing *)\n\nassoc = \
+                  <|\n\"a\" -> 1\n|>\ns = \"line\nbreak\"\nn++\n(* only a comment *)\nPlot[f[x], \
+                  {x, 0, 1}]";
+    assert_eq!(split_expressions(source), vec![
+      "(* setup *)\nf[x_] :=\n  x^2 +\n  1",
+      "g = {1,\n 2}; (* trailing *)",
+      "assoc = <|\n\"a\" -> 1\n|>",
+      "s = \"line\nbreak\"",
+      "n++",
+      "(* only a comment *)\nPlot[f[x], {x, 0, 1}]",
+    ]);
+  }
+
+  #[test]
+  fn comment_only_sources_have_no_expressions() {
+    assert!(split_expressions("(* a (* nested *) comment *)\n\n").is_empty());
+  }
+}
@@ -15,104 +15,380 @@ use chrono::{
 use flume::Sender;
 use schemars::JsonSchema;
 use serde::Serialize;
-use tokio::sync::Mutex;
+use tokio::sync::{
+  Mutex,
+  OwnedSemaphorePermit,
+  Semaphore
+};
+use tokio_util::sync::CancellationToken;
 
+use crate::backend::{
+  Kernel,
+  KernelBackend,
+  WstpBackend
+};
+use crate::config::{
+  Config,
+  KernelConfig
+};
+use crate::history::History;
+use crate::jobs::{
+  Job,
+  JobTable
+};
+use crate::pool::{
+  KernelPool,
+  PoolConfig,
+  PoolStatus
+};
 use crate::session_id::SessionIdSigner;
 use crate::wolfram::{
-  self,
-  EvalResult
+  AbortSignal,
+  EvalOptions,
+  EvalResult,
+  GraphicsOptions,
+  LicenseExhausted,
+  LinkDead
 };
 
+/// How long to wait for the kernel to acknowledge an abort after an eval
+/// timeout or cancellation before giving up on the reply.
+const ABORT_GRACE: Duration = Duration::from_secs(10);
+
 #[derive(Debug)]
 pub enum SessionRequest {
   Eval {
-    code:  String,
-    reply: tokio::sync::oneshot::Sender<anyhow::Result<EvalResult>>
+    code:    String,
+    options: EvalOptions,
+    /// Raised to abort this evaluation, and only this one.
+    abort:   AbortSignal,
+    reply:   tokio::sync::oneshot::Sender<anyhow::Result<EvalResult>>
+  },
+  /// Record how to bring the kernel back if it dies, and run `code` now.
+  Setup {
+    code:    Option<String>,
+    restart: bool,
+    reply:   tokio::sync::oneshot::Sender<anyhow::Result<()>>
   },
   Shutdown {
     reply: tokio::sync::oneshot::Sender<()>
   }
 }
 
+/// Where a session's kernel is in its life.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+  /// The kernel is launching, or relaunching after it died.
+  #[default]
+  Starting,
+  /// Waiting for code.
+  Ready,
+  /// Evaluating.
+  Busy,
+  /// The kernel died and was not (or could not be) restarted.
+  Dead
+}
+
+#[derive(Debug, Default)]
+pub struct KernelStatus {
+  pub state:    SessionState,
+  /// How many times the kernel has been relaunched after dying.
+  pub restarts: u32
+}
+
+/// A worker's [`KernelStatus`], updated by its thread.
+type SharedStatus = Arc<std::sync::Mutex<KernelStatus>>;
+
 #[derive(Debug)]
 pub struct SessionHandle {
   pub created_at:    DateTime<Utc>,
   pub last_accessed: Arc<AtomicI64>,
   pub tx:            Sender<SessionRequest>,
-  join:              thread::JoinHandle<()>
+  /// Graphics export defaults for every evaluation in this session.
+  pub graphics:      GraphicsOptions,
+  /// The client that created the session, or `None` for sessions created by
+  /// an [`Caller::Admin`].
+  pub owner:         Option<String>,
+  /// Every evaluation so far.
+  pub history:       History,
+  status:            SharedStatus,
+  join:              thread::JoinHandle<()>,
+  /// Held for as long as the session is open when sessions are capped.
+  _seat:             Option<OwnedSemaphorePermit>
+}
+
+/// A launched kernel and the thread that owns it, not yet bound to a session.
+#[derive(Debug)]
+pub(crate) struct Worker {
+  tx:     Sender<SessionRequest>,
+  abort:  AbortSignal,
+  status: SharedStatus,
+  join:   thread::JoinHandle<()>
 }
 
 #[derive(Clone)]
-pub struct SessionManager {
-  signer: SessionIdSigner,
-  inner:  Arc<Mutex<HashMap<String, SessionHandle>>>
+pub struct SessionManager<B: KernelBackend = WstpBackend> {
+  backend: B,
+  signer:  SessionIdSigner,
+  pool:    KernelPool,
+  /// One permit per session allowed by `max_sessions`. Its waiters form the
+  /// create queue.
+  seats:   Option<Arc<Semaphore>>,
+  options: Arc<SessionOptions>,
+  inner:   Arc<Mutex<HashMap<String, SessionHandle>>>,
+  jobs:    JobTable
+}
+
+#[derive(Clone, Debug)]
+pub struct SessionOptions {
+  pub pool:             PoolConfig,
+  /// Close sessions idle for longer than this.
+  pub idle_timeout:     Duration,
+  /// How often to look for idle sessions.
+  pub cleanup_interval: Duration,
+  /// Eval timeout for calls that do not set their own.
+  pub eval_timeout:     Duration,
+  /// Most sessions open at once.
+  pub max_sessions:     Option<usize>,
+  /// How long a create request waits for a seat when `max_sessions` are open.
+  /// Zero rejects it at once.
+  pub queue_timeout:    Duration,
+  /// Relaunch a session's kernel when it dies, replaying its setup code.
+  pub auto_restart:     bool,
+  /// How long finished jobs are kept.
+  pub job_retention:    Duration,
+  /// Most evaluations kept in each session's history.
+  pub history_limit:    usize,
+  /// Graphics export defaults, under each session's own.
+  pub graphics:         GraphicsOptions,
+  pub kernel:           KernelConfig
+}
+
+impl Default for SessionOptions {
+  fn default() -> Self {
+    Config::default().session_options()
+  }
+}
+
+/// Who is acting on a session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Caller {
+  /// An MCP client connection, identified by an id unique to the connection.
+  /// It can only see and use the sessions it created.
+  Client(String),
+  /// Can see and use every session: the REPL, or an MCP client presenting the
+  /// admin token.
+  Admin
+}
+
+impl Caller {
+  /// Whether this caller may use something belonging to `owner`, a client id
+  /// or `None` for an admin's.
+  fn owns(
+    &self,
+    owner: Option<&String>
+  ) -> bool {
+    match self {
+      | Self::Client(id) => owner == Some(id),
+      | Self::Admin => true
+    }
+  }
+}
+
+/// How to create a session.
+#[derive(Clone, Debug, Default)]
+pub struct CreateOptions {
+  /// Graphics export defaults for the session's evaluations, over the
+  /// configured ones.
+  pub graphics:      GraphicsOptions,
+  /// Overrides [`SessionOptions::queue_timeout`].
+  pub queue_timeout: Option<Duration>,
+  /// Code run when the session starts, and again whenever its kernel is
+  /// restarted.
+  pub setup_code:    Option<String>
+}
+
+/// Why a session could not be created.
+#[derive(Debug)]
+pub enum CreateError {
+  /// `max_sessions` sessions are open and none closed before the queue
+  /// deadline.
+  Capacity {
+    max_sessions: usize,
+    waited:       Duration
+  },
+  /// The kernel could not get a license.
+  LicenseExhausted(LicenseExhausted),
+  /// Invalid options, or the kernel failed to launch for another reason.
+  Other(anyhow::Error)
+}
+
+impl CreateError {
+  /// A stable name for the error, for clients to match on.
+  pub fn kind(&self) -> &'static str {
+    match self {
+      | Self::Capacity {
+        ..
+      } => "capacity",
+      | Self::LicenseExhausted(_) => "license_exhausted",
+      | Self::Other(_) => "failed"
+    }
+  }
+}
+
+impl std::fmt::Display for CreateError {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>
+  ) -> std::fmt::Result {
+    match self {
+      | Self::Capacity {
+        max_sessions,
+        waited
+      } => {
+        write!(
+          f,
+          "{}: all {max_sessions} sessions are in use and none closed within {}s; close a session \
+           or try again later",
+          self.kind(),
+          waited.as_secs()
+        )
+      }
+      | Self::LicenseExhausted(e) => write!(f, "{}: {e}", self.kind()),
+      | Self::Other(e) => write!(f, "{e}")
+    }
+  }
+}
+
+impl std::error::Error for CreateError {}
+
+impl From<anyhow::Error> for CreateError {
+  fn from(e: anyhow::Error) -> Self {
+    match e.downcast::<LicenseExhausted>() {
+      | Ok(e) => Self::LicenseExhausted(e),
+      | Err(e) => Self::Other(e)
+    }
+  }
 }
 
 #[derive(Debug, Serialize, JsonSchema)]
 pub struct SessionInfo {
   pub session_id:     String,
   pub created_at_utc: String,
-  pub idle_seconds:   i64
+  pub idle_seconds:   i64,
+  pub state:          SessionState,
+  /// How many times the kernel died and was relaunched.
+  pub restarts:       u32
 }
 
 impl SessionManager {
-  pub fn new() -> Self {
+  pub fn new(options: SessionOptions) -> Self {
+    Self::with_options(WstpBackend::new(options.kernel.clone()), options)
+  }
+}
+
+impl<B: KernelBackend> SessionManager<B> {
+  #[cfg(test)]
+  pub fn with_backend(backend: B) -> Self {
+    Self::with_options(backend, SessionOptions::default())
+  }
+
+  pub fn with_options(
+    backend: B,
+    options: SessionOptions
+  ) -> Self {
     let signer = SessionIdSigner::from_env();
     let inner = Arc::new(Mutex::new(HashMap::new()));
     let manager = Self {
+      backend,
       signer,
-      inner
+      pool: KernelPool::new(options.pool.clone()),
+      seats: options.max_sessions.map(|max| Arc::new(Semaphore::new(max))),
+      options: Arc::new(options),
+      inner,
+      jobs: JobTable::default()
     };
 
+    // Warm the pool up front so the first session is instant too.
+    manager.pool.refill(&manager.backend, 0);
+
     // Background task for idle cleanup
-    let cleanup_inner = manager.inner.clone();
+    let cleanup = manager.clone();
     tokio::spawn(async move {
-      let mut interval = tokio::time::interval(Duration::from_secs(60));
+      let mut interval = tokio::time::interval(cleanup.options.cleanup_interval);
       loop {
         interval.tick().await;
-        let now = Utc::now().timestamp();
-        let mut to_remove = Vec::new();
+        cleanup.close_idle_sessions(cleanup.options.idle_timeout).await;
+        cleanup.jobs.prune(cleanup.options.job_retention);
+      }
+    });
 
-        {
-          let map = cleanup_inner.lock().await;
-          for (id, handle) in map.iter() {
-            let last = handle.last_accessed.load(Ordering::SeqCst);
-            if now - last > 1800 {
-              // 30 minutes
-              to_remove.push(id.clone());
-            }
-          }
-        }
+    manager
+  }
 
-        for id in to_remove {
-          tracing::info!(session_id = %id, "closing idle session");
-          // We don't want to hold the lock while joining threads,
-          // but close_session handles its own locking.
-          // Wait, we need a way to close without double locking or deadlocking.
-          // Let's just remove and shutdown here.
-          let handle = {
-            let mut map = cleanup_inner.lock().await;
-            map.remove(&id)
-          };
+  /// Eval timeout for calls that do not set their own.
+  pub fn eval_timeout(&self) -> Duration {
+    self.options.eval_timeout
+  }
 
-          if let Some(h) = handle {
-            let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
-            if h
-              .tx
-              .send(SessionRequest::Shutdown {
-                reply: reply_tx
-              })
-              .is_ok()
-            {
-              let _ = reply_rx.await;
-            }
-            let _ = tokio::task::spawn_blocking(move || h.join.join()).await;
-          }
+  /// Shut down every session that has been idle for longer than `max_idle`.
+  pub async fn close_idle_sessions(
+    &self,
+    max_idle: Duration
+  ) {
+    let now = Utc::now().timestamp();
+    let max_idle = max_idle.as_secs() as i64;
+    self
+      .close_where("idle", |handle| now - handle.last_accessed.load(Ordering::SeqCst) > max_idle)
+      .await;
+  }
+
+  /// Shut down every session created by `client`, once it has disconnected.
+  pub async fn close_client_sessions(
+    &self,
+    client: &str
+  ) {
+    self
+      .close_where("disconnected client's", |handle| handle.owner.as_deref() == Some(client))
+      .await;
+  }
+
+  async fn close_where(
+    &self,
+    reason: &str,
+    predicate: impl Fn(&SessionHandle) -> bool
+  ) {
+    let to_remove: Vec<String> = {
+      let map = self.inner.lock().await;
+      map.iter().filter(|(_, handle)| predicate(handle)).map(|(id, _)| id.clone()).collect()
+    };
+
+    for id in to_remove {
+      tracing::info!(session_id = %id, "closing {reason} session");
+      // Remove under the lock, then shut down without holding it so the
+      // worker join cannot stall other callers.
+      let handle = {
+        let mut map = self.inner.lock().await;
+        map.remove(&id)
+      };
+
+      if let Some(h) = handle {
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+        if h
+          .tx
+          .send(SessionRequest::Shutdown {
+            reply: reply_tx
+          })
+          .is_ok()
+        {
+          let _ = reply_rx.await;
         }
+        let _ = tokio::task::spawn_blocking(move || h.join.join()).await;
       }
-    });
+    }
 
-    manager
+    self.refill_pool().await;
   }
 
   pub fn verify(
@@ -122,63 +398,123 @@ impl SessionManager {
     self.signer.verify(session_id)
   }
 
-  pub async fn create_session(&self) -> anyhow::Result<String> {
-    let session_id = self.signer.generate();
-    let kernel_cmd = wolfram::resolve_kernel_cmd()?;
+  pub async fn create_session(&self) -> Result<String, CreateError> {
+    self.create_session_with(&Caller::Admin, CreateOptions::default()).await
+  }
 
-    let (tx, rx) = flume::unbounded::<SessionRequest>();
-    let (ready_tx, ready_rx) = std::sync::mpsc::channel::<anyhow::Result<()>>();
+  /// Create a session owned by `caller`. When `max_sessions` are open, wait in
+  /// line for one to close, up to the queue timeout.
+  pub async fn create_session_with(
+    &self,
+    caller: &Caller,
+    options: CreateOptions
+  ) -> Result<String, CreateError> {
+    let graphics = options.graphics.or(&self.options.graphics);
+    graphics.export_args()?;
+    let seat = match &self.seats {
+      | Some(seats) => {
+        Some(
+          self
+            .take_seat(seats, options.queue_timeout.unwrap_or(self.options.queue_timeout))
+            .await?
+        )
+      }
+      | None => None
+    };
+    let session_id = self.signer.generate();
 
-    let join = thread::spawn(move || {
-      let mut kernel = match wolfram::launch_link(&kernel_cmd) {
-        | Ok(k) => {
-          let _ = ready_tx.send(Ok(()));
-          k
-        }
-        | Err(e) => {
-          let _ = ready_tx.send(Err(e));
-          return;
-        }
-      };
-      let link = kernel.link();
-
-      tracing::info!("session thread started");
-
-      while let Ok(req) = rx.recv() {
-        match req {
-          | SessionRequest::Eval {
-            code,
-            reply
-          } => {
-            let res = wolfram::evaluate(link, &code);
-            let _ = reply.send(res);
-          }
-          | SessionRequest::Shutdown {
-            reply
-          } => {
-            tracing::info!("session thread shutting down");
-            let _ = reply.send(());
-            break;
-          }
-        }
+    let worker = match self.pool.take() {
+      | Some(worker) => {
+        tracing::debug!("using pre-warmed kernel from pool");
+        worker
       }
+      | None => spawn_worker(self.backend.clone()).await?
+    };
 
-      tracing::info!("session thread exited");
-    });
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    let setup = SessionRequest::Setup {
+      code:    options.setup_code.clone(),
+      restart: self.options.auto_restart,
+      reply:   reply_tx
+    };
+    let timeout = self.options.eval_timeout;
+    if let Err(e) =
+      call_worker(&session_id, &worker.tx, &worker.abort, setup, reply_rx, timeout, None).await
+    {
+      worker.shut_down().await;
+      self.refill_pool().await;
+      return Err(match options.setup_code {
+        | Some(_) => CreateError::Other(anyhow!("the session's setup code failed: {e}")),
+        | None => e.into()
+      });
+    }
 
-    match ready_rx.recv() {
-      | Ok(Ok(())) => {
-        let mut map = self.inner.lock().await;
-        map.insert(session_id.clone(), SessionHandle {
-          created_at: Utc::now(),
-          last_accessed: Arc::new(AtomicI64::new(Utc::now().timestamp())),
-          tx,
-          join
-        });
-        Ok(session_id)
+    {
+      let mut map = self.inner.lock().await;
+      map.insert(session_id.clone(), SessionHandle {
+        created_at: Utc::now(),
+        last_accessed: Arc::new(AtomicI64::new(Utc::now().timestamp())),
+        tx: worker.tx,
+        graphics,
+        owner: match caller {
+          | Caller::Client(id) => Some(id.clone()),
+          | Caller::Admin => None
+        },
+        history: History::with_limit(self.options.history_limit),
+        status: worker.status,
+        join: worker.join,
+        _seat: seat
+      });
+    }
+
+    self.refill_pool().await;
+    Ok(session_id)
+  }
+
+  /// A seat for a new session, waiting up to `wait` for one to free up.
+  /// Waiters are served first come, first served.
+  async fn take_seat(
+    &self,
+    seats: &Arc<Semaphore>,
+    wait: Duration
+  ) -> Result<OwnedSemaphorePermit, CreateError> {
+    if let Ok(seat) = seats.clone().try_acquire_owned() {
+      return Ok(seat);
+    }
+    if !wait.is_zero() {
+      tracing::info!(wait_secs = wait.as_secs(), "session limit reached; queueing create request");
+      if let Ok(Ok(seat)) = tokio::time::timeout(wait, seats.clone().acquire_owned()).await {
+        return Ok(seat);
       }
-      | Ok(Err(e)) => Err(e),
-      | Err(e) => Err(anyhow!("session startup channel failed: {e:?}"))
+    }
+    Err(CreateError::Capacity {
+      max_sessions: self.options.max_sessions.unwrap_or_default(),
+      waited:       wait
+    })
+  }
+
+  /// Top the pool back up to its minimum in the background.
+  async fn refill_pool(&self) {
+    let active = self.inner.lock().await.len();
+    self.pool.refill(&self.backend, active);
+  }
+
+  pub async fn pool_status(&self) -> PoolStatus {
+    let active = self.inner.lock().await.len();
+    self.pool.status(active)
+  }
+
+  /// Fail unless `caller` may use `session_id`. Sessions of other clients are
+  /// reported as not found, so ids cannot be probed.
+  pub async fn authorize(
+    &self,
+    session_id: &str,
+    caller: &Caller
+  ) -> anyhow::Result<()> {
+    let map = self.inner.lock().await;
+    match map.get(session_id) {
+      | Some(h) if caller.owns(h.owner.as_ref()) => Ok(()),
+      | _ => Err(anyhow!("session not found or closed: {session_id}"))
     }
   }
 
@@ -186,29 +522,143 @@ impl SessionManager {
     &self,
     session_id: &str,
     code: &str,
+    options: &EvalOptions,
     timeout: Duration
   ) -> anyhow::Result<EvalResult> {
-    let handle = {
+    self.run_eval(session_id, code, options, timeout, None, true).await
+  }
+
+  /// Like [`eval`](Self::eval), but aborts the evaluation when `cancel` is
+  /// given and gets cancelled. The result is then `aborted`, as after a
+  /// timeout.
+  pub async fn eval_cancellable(
+    &self,
+    session_id: &str,
+    code: &str,
+    options: &EvalOptions,
+    timeout: Duration,
+    cancel: Option<&CancellationToken>
+  ) -> anyhow::Result<EvalResult> {
+    self.run_eval(session_id, code, options, timeout, cancel, true).await
+  }
+
+  /// Like [`eval`](Self::eval), but leaves the session's history alone; for
+  /// evaluations the server runs on the session's behalf, such as exports.
+  pub async fn eval_unrecorded(
+    &self,
+    session_id: &str,
+    code: &str,
+    options: &EvalOptions,
+    timeout: Duration
+  ) -> anyhow::Result<EvalResult> {
+    self.run_eval(session_id, code, options, timeout, None, false).await
+  }
+
+  async fn run_eval(
+    &self,
+    session_id: &str,
+    code: &str,
+    options: &EvalOptions,
+    timeout: Duration,
+    cancel: Option<&CancellationToken>,
+    record: bool
+  ) -> anyhow::Result<EvalResult> {
+    let (handle, graphics, history) = {
       let map = self.inner.lock().await;
       let h =
         map.get(session_id).ok_or_else(|| anyhow!("session not found or closed: {session_id}"))?;
       h.last_accessed.store(Utc::now().timestamp(), Ordering::SeqCst);
-      h.tx.clone()
+      (h.tx.clone(), options.graphics.clone().or(&h.graphics), h.history.clone())
     };
 
+    let abort = AbortSignal::default();
     let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
-    handle
-      .send(SessionRequest::Eval {
-        code:  code.to_string(),
-        reply: reply_tx
-      })
-      .map_err(|e| anyhow!("failed to send eval request: {e:?}"))?;
-
-    match tokio::time::timeout(timeout, reply_rx).await {
-      | Ok(Ok(res)) => res,
-      | Ok(Err(e)) => Err(anyhow!("eval reply canceled: {e:?}")),
-      | Err(_) => Err(anyhow!("evaluation timed out after {timeout:?}"))
+    let request = SessionRequest::Eval {
+      code:    code.to_string(),
+      options: EvalOptions {
+        graphics,
+        ..options.clone()
+      },
+      abort:   abort.clone(),
+      reply:   reply_tx
+    };
+    let started_at = Utc::now();
+    let started = std::time::Instant::now();
+    let res = call_worker(session_id, &handle, &abort, request, reply_rx, timeout, cancel).await;
+    if record {
+      history.record(code, started_at, started.elapsed(), &res);
     }
+    res
+  }
+
+  /// The session's evaluation history.
+  pub async fn history(
+    &self,
+    session_id: &str
+  ) -> anyhow::Result<History> {
+    let map = self.inner.lock().await;
+    let h =
+      map.get(session_id).ok_or_else(|| anyhow!("session not found or closed: {session_id}"))?;
+    Ok(h.history.clone())
+  }
+
+  /// Start evaluating `code` in the background and return the job's id. The
+  /// evaluation queues behind any other on the session and is recorded in its
+  /// history like any other.
+  pub async fn submit(
+    &self,
+    caller: &Caller,
+    session_id: &str,
+    code: &str,
+    options: EvalOptions,
+    timeout: Duration
+  ) -> anyhow::Result<String> {
+    self.authorize(session_id, caller).await?;
+    let job = Job::new(
+      session_id,
+      match caller {
+        | Caller::Client(id) => Some(id.clone()),
+        | Caller::Admin => None
+      },
+      code
+    );
+    let id = job.id.clone();
+    let cancel = job.cancel_token();
+    self.jobs.insert(job);
+
+    let manager = self.clone();
+    let (job_id, session_id, code) = (id.clone(), session_id.to_string(), code.to_string());
+    tokio::spawn(async move {
+      let started = std::time::Instant::now();
+      let res =
+        manager.eval_cancellable(&session_id, &code, &options, timeout, Some(&cancel)).await;
+      manager.jobs.finish(&job_id, res, started.elapsed());
+    });
+    Ok(id)
+  }
+
+  /// A job the caller submitted.
+  pub fn job(
+    &self,
+    caller: &Caller,
+    job_id: &str
+  ) -> anyhow::Result<Job> {
+    self
+      .jobs
+      .get(job_id)
+      .ok()
+      .filter(|job| caller.owns(job.owner.as_ref()))
+      .ok_or_else(|| anyhow!("job not found or expired: {job_id}"))
+  }
+
+  /// Cancel a running job, aborting its evaluation.
+  pub fn cancel_job(
+    &self,
+    caller: &Caller,
+    job_id: &str
+  ) -> anyhow::Result<Job> {
+    self.job(caller, job_id)?;
+    self.jobs.cancel(job_id)
   }
 
   pub async fn close_session(
@@ -238,22 +688,771 @@ impl SessionManager {
     .await
     .map_err(|e| anyhow!("failed to join session thread: {e:?}"))?;
 
+    self.refill_pool().await;
     Ok(())
   }
 
   pub async fn list_sessions(&self) -> Vec<SessionInfo> {
+    self.list_sessions_for(&Caller::Admin).await
+  }
+
+  /// The sessions `caller` may use.
+  pub async fn list_sessions_for(
+    &self,
+    caller: &Caller
+  ) -> Vec<SessionInfo> {
     let now = Utc::now().timestamp();
     let map = self.inner.lock().await;
     map
       .iter()
+      .filter(|(_, h)| caller.owns(h.owner.as_ref()))
       .map(|(id, h)| {
         let last = h.last_accessed.load(Ordering::SeqCst);
+        let status = h.status.lock().unwrap();
         SessionInfo {
           session_id:     id.clone(),
           created_at_utc: h.created_at.to_rfc3339(),
-          idle_seconds:   now - last
+          idle_seconds:   now - last,
+          state:          status.state,
+          restarts:       status.restarts
         }
       })
       .collect()
   }
 }
+
+impl Worker {
+  /// Stop the kernel and wait for the thread to exit.
+  async fn shut_down(self) {
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    if self
+      .tx
+      .send(SessionRequest::Shutdown {
+        reply: reply_tx
+      })
+      .is_ok()
+    {
+      let _ = reply_rx.await;
+    }
+    let _ = tokio::task::spawn_blocking(move || self.join.join()).await;
+  }
+}
+
+/// Send `request` to a session worker and wait up to `timeout` for its reply.
+/// When the deadline passes or `cancel` is cancelled, raise `abort` so the
+/// session is not left wedged behind a runaway computation, then wait for the
+/// kernel to come back.
+async fn call_worker<T>(
+  session_id: &str,
+  tx: &Sender<SessionRequest>,
+  abort: &AbortSignal,
+  request: SessionRequest,
+  mut reply_rx: tokio::sync::oneshot::Receiver<anyhow::Result<T>>,
+  timeout: Duration,
+  cancel: Option<&CancellationToken>
+) -> anyhow::Result<T> {
+  tx.send(request).map_err(|e| anyhow!("failed to send request to the session: {e:?}"))?;
+
+  let cancelled = async {
+    match cancel {
+      | Some(cancel) => cancel.cancelled().await,
+      | None => std::future::pending().await
+    }
+  };
+  let reason = tokio::select! {
+    res = &mut reply_rx => {
+      return res.unwrap_or_else(|e| Err(anyhow!("eval reply canceled: {e:?}")));
+    }
+    _ = tokio::time::sleep(timeout) => format!("timed out after {timeout:?}"),
+    _ = cancelled => "was cancelled".to_string()
+  };
+
+  tracing::warn!(session_id = %session_id, "evaluation {reason}; aborting");
+  abort.request();
+  match tokio::time::timeout(ABORT_GRACE, reply_rx).await {
+    | Ok(Ok(res)) => res,
+    | Ok(Err(e)) => Err(anyhow!("eval reply canceled: {e:?}")),
+    | Err(_) => Err(anyhow!("evaluation {reason} and the kernel did not acknowledge the abort"))
+  }
+}
+
+/// Launch a kernel on a dedicated thread and wait until it is ready to
+/// evaluate.
+pub(crate) async fn spawn_worker<B: KernelBackend>(backend: B) -> anyhow::Result<Worker> {
+  let abort = AbortSignal::default();
+  let status = SharedStatus::default();
+  let (tx, rx) = flume::unbounded::<SessionRequest>();
+  let (ready_tx, ready_rx) = tokio::sync::oneshot::channel::<anyhow::Result<()>>();
+
+  let thread_status = status.clone();
+  let thread_abort = abort.clone();
+  let join = thread::spawn(move || {
+    let mut thread_state = WorkerThread {
+      backend,
+      kernel: None,
+      status: thread_status,
+      abort: thread_abort,
+      setup: None,
+      restart: false
+    };
+    match thread_state.backend.launch() {
+      | Ok(k) => {
+        thread_state.kernel = Some(k);
+        thread_state.set_state(SessionState::Ready);
+        let _ = ready_tx.send(Ok(()));
+      }
+      | Err(e) => {
+        let _ = ready_tx.send(Err(e));
+        return;
+      }
+    }
+    tracing::info!("session thread started");
+    thread_state.run(rx);
+    tracing::info!("session thread exited");
+  });
+
+  match ready_rx.await {
+    | Ok(Ok(())) => {
+      Ok(Worker {
+        tx,
+        abort,
+        status,
+        join
+      })
+    }
+    | Ok(Err(e)) => Err(e),
+    | Err(e) => Err(anyhow!("session startup channel failed: {e:?}"))
+  }
+}
+
+/// What a session worker thread owns: the kernel, and what it needs to bring
+/// a dead kernel back.
+struct WorkerThread<B: KernelBackend> {
+  backend: B,
+  /// `None` once the kernel has died and until it is relaunched.
+  kernel:  Option<B::Kernel>,
+  status:  SharedStatus,
+  abort:   AbortSignal,
+  /// Code to replay on a relaunched kernel.
+  setup:   Option<String>,
+  /// Relaunch the kernel when it dies.
+  restart: bool
+}
+
+impl<B: KernelBackend> WorkerThread<B> {
+  fn run(
+    mut self,
+    rx: flume::Receiver<SessionRequest>
+  ) {
+    while let Ok(req) = rx.recv() {
+      match req {
+        | SessionRequest::Eval {
+          code,
+          options,
+          abort,
+          reply
+        } => {
+          let _ = reply.send(self.eval(&code, &options, &abort));
+        }
+        | SessionRequest::Setup {
+          code,
+          restart,
+          reply
+        } => {
+          self.restart = restart;
+          self.setup = code.clone();
+          self.abort.reset();
+          let abort = self.abort.clone();
+          let res = match code {
+            | Some(code) => {
+              self.eval(&code, &EvalOptions::default(), &abort).and_then(|res| setup_outcome(&res))
+            }
+            | None => self.ensure_kernel(&abort)
+          };
+          let _ = reply.send(res);
+        }
+        | SessionRequest::Shutdown {
+          reply
+        } => {
+          tracing::info!("session thread shutting down");
+          let _ = reply.send(());
+          break;
+        }
+      }
+    }
+  }
+
+  fn set_state(
+    &self,
+    state: SessionState
+  ) {
+    self.status.lock().unwrap().state = state;
+  }
+
+  fn eval(
+    &mut self,
+    code: &str,
+    options: &EvalOptions,
+    abort: &AbortSignal
+  ) -> anyhow::Result<EvalResult> {
+    self.ensure_kernel(abort)?;
+    let kernel = self.kernel.as_mut().expect("ensure_kernel leaves a kernel");
+    self.status.lock().unwrap().state = SessionState::Busy;
+    match kernel.evaluate(code, options, abort) {
+      | Err(e) if e.is::<LinkDead>() => {
+        tracing::warn!("session kernel died: {e}");
+        self.kernel = None;
+        self.set_state(SessionState::Dead);
+        if !self.restart {
+          return Err(anyhow!("{e}; close the session and create a new one"));
+        }
+        match self.relaunch(abort) {
+          | Ok(()) => {
+            Err(anyhow!(
+              "{e}. The kernel was restarted: earlier definitions are lost, but the session's \
+               setup code was replayed"
+            ))
+          }
+          | Err(restart) => Err(anyhow!("{e}, and restarting it failed: {restart:#}"))
+        }
+      }
+      | res => {
+        self.set_state(SessionState::Ready);
+        res
+      }
+    }
+  }
+
+  /// Make sure there is a live kernel, relaunching a dead one when restarts
+  /// are on. `abort` cuts a hung setup replay short.
+  fn ensure_kernel(
+    &mut self,
+    abort: &AbortSignal
+  ) -> anyhow::Result<()> {
+    if self.kernel.as_mut().is_some_and(|k| !k.is_alive()) {
+      tracing::warn!("session kernel exited");
+      self.kernel = None;
+      self.set_state(SessionState::Dead);
+    }
+    if self.kernel.is_some() {
+      return Ok(());
+    }
+    if !self.restart {
+      return Err(anyhow!("the session's kernel is dead; close the session and create a new one"));
+    }
+    self
+      .relaunch(abort)
+      .map_err(|e| anyhow!("the session's kernel is dead and restarting it failed: {e:#}"))
+  }
+
+  /// Launch a new kernel and replay the setup code on it, under the abort
+  /// signal of the request that needed the kernel.
+  fn relaunch(
+    &mut self,
+    abort: &AbortSignal
+  ) -> anyhow::Result<()> {
+    self.set_state(SessionState::Starting);
+    tracing::info!("restarting session kernel");
+    let launched = self.backend.launch().and_then(|mut kernel| {
+      if let Some(code) = &self.setup {
+        kernel
+          .evaluate(code, &EvalOptions::default(), abort)
+          .and_then(|res| setup_outcome(&res))
+          .map_err(|e| anyhow!("replaying the setup code failed: {e}"))?;
+      }
+      Ok(kernel)
+    });
+    match launched {
+      | Ok(kernel) => {
+        self.kernel = Some(kernel);
+        let mut status = self.status.lock().unwrap();
+        status.state = SessionState::Ready;
+        status.restarts += 1;
+        Ok(())
+      }
+      | Err(e) => {
+        self.set_state(SessionState::Dead);
+        Err(e)
+      }
+    }
+  }
+}
+
+/// Whether setup code ran cleanly. The kernel reports bad setup code, such as
+/// a missing package, as `$Failed` or messages rather than an error.
+fn setup_outcome(res: &EvalResult) -> anyhow::Result<()> {
+  if res.aborted {
+    return Err(anyhow!("it was aborted"));
+  }
+  if !res.messages.is_empty() {
+    let messages: Vec<String> =
+      res.messages.iter().map(|m| format!("{}::{}: {}", m.symbol, m.tag, m.text)).collect();
+    return Err(anyhow!("{}", messages.join("; ")));
+  }
+  if res.output == "$Failed" {
+    return Err(anyhow!("it returned $Failed"));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::backend::mock::{
+    MockBackend,
+    MockReply
+  };
+  use crate::jobs::JobState;
+  use crate::wolfram::{
+    GraphicsFormat,
+    InputChannel,
+    InputKind,
+    InputRequest
+  };
+
+  const TIMEOUT: Duration = Duration::from_secs(5);
+
+  #[tokio::test]
+  async fn create_eval_close_lifecycle() {
+    let backend = MockBackend::new().on("1+1", MockReply::output("2").with_logs(&["hi"]));
+    let manager = SessionManager::with_backend(backend.clone());
+
+    let id = manager.create_session().await.unwrap();
+    assert!(manager.verify(&id));
+    assert_eq!(backend.launches(), 1);
+
+    let res = manager.eval(&id, "1+1", &EvalOptions::default(), TIMEOUT).await.unwrap();
+    assert_eq!(res.output, "2");
+    assert_eq!(res.logs, vec!["hi".to_string()]);
+
+    let listed = manager.list_sessions().await;
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].session_id, id);
+
+    manager.close_session(&id).await.unwrap();
+    assert!(manager.list_sessions().await.is_empty());
+    assert!(manager.eval(&id, "1+1", &EvalOptions::default(), TIMEOUT).await.is_err());
+    assert!(manager.close_session(&id).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn launch_failure_is_reported() {
+    let manager = SessionManager::with_backend(MockBackend::new().fail_launch("kernel not found"));
+    let err = manager.create_session().await.unwrap_err();
+    assert_eq!(err.kind(), "failed");
+    assert!(err.to_string().contains("kernel not found"), "{err}");
+    assert!(manager.list_sessions().await.is_empty());
+
+    let manager = SessionManager::with_backend(
+      MockBackend::new().fail_launch("The number of licensed processes has been exceeded")
+    );
+    let err = manager.create_session().await.unwrap_err();
+    assert!(matches!(err, CreateError::LicenseExhausted(_)), "{err}");
+    assert!(err.to_string().contains("licensed processes"), "{err}");
+  }
+
+  fn setup(code: &str) -> CreateOptions {
+    CreateOptions {
+      setup_code: Some(code.to_string()),
+      ..CreateOptions::default()
+    }
+  }
+
+  async fn status(manager: &SessionManager<MockBackend>) -> (SessionState, u32) {
+    let info = &manager.list_sessions().await[0];
+    (info.state, info.restarts)
+  }
+
+  #[tokio::test]
+  async fn dead_kernels_are_restarted_with_their_setup_code() {
+    let backend = MockBackend::new().on("crash", MockReply::crash());
+    let manager = SessionManager::with_backend(backend.clone());
+    let id = manager.create_session_with(&Caller::Admin, setup("f[x_] := x^2")).await.unwrap();
+    assert_eq!(status(&manager).await, (SessionState::Ready, 0));
+
+    let err = manager.eval(&id, "crash", &EvalOptions::default(), TIMEOUT).await.unwrap_err();
+    assert!(err.to_string().contains("link to the kernel died"), "{err}");
+    assert!(err.to_string().contains("restarted"), "{err}");
+    assert_eq!(status(&manager).await, (SessionState::Ready, 1));
+    assert_eq!(backend.launches(), 2);
+    assert_eq!(backend.evaluations(), vec!["f[x_] := x^2", "crash", "f[x_] := x^2"]);
+
+    // A kernel that exits between evaluations is relaunched before the next.
+    backend.kill_kernels();
+    let res = manager.eval(&id, "1+1", &EvalOptions::default(), TIMEOUT).await.unwrap();
+    assert_eq!(res.output, "1+1");
+    assert_eq!(status(&manager).await, (SessionState::Ready, 2));
+  }
+
+  #[tokio::test]
+  async fn dead_sessions_stay_dead_without_restarts() {
+    let backend = MockBackend::new().on("crash", MockReply::crash());
+    let manager = SessionManager::with_options(backend.clone(), SessionOptions {
+      auto_restart: false,
+      ..SessionOptions::default()
+    });
+    let id = manager.create_session().await.unwrap();
+
+    let err = manager.eval(&id, "crash", &EvalOptions::default(), TIMEOUT).await.unwrap_err();
+    assert!(err.to_string().contains("create a new one"), "{err}");
+    assert_eq!(status(&manager).await, (SessionState::Dead, 0));
+
+    let err = manager.eval(&id, "1+1", &EvalOptions::default(), TIMEOUT).await.unwrap_err();
+    assert!(err.to_string().contains("kernel is dead"), "{err}");
+    assert_eq!(backend.launches(), 1);
+  }
+
+  #[tokio::test]
+  async fn failed_restarts_are_retried_on_the_next_evaluation() {
+    let backend = MockBackend::new();
+    let manager = SessionManager::with_backend(backend.clone());
+    let id = manager.create_session().await.unwrap();
+
+    backend.kill_kernels();
+    let backend = backend.fail_launch("kernel not found");
+    let err = manager.eval(&id, "1+1", &EvalOptions::default(), TIMEOUT).await.unwrap_err();
+    assert!(err.to_string().contains("restarting it failed"), "{err}");
+    assert_eq!(status(&manager).await, (SessionState::Dead, 0));
+
+    backend.allow_launch();
+    manager.eval(&id, "1+1", &EvalOptions::default(), TIMEOUT).await.unwrap();
+    assert_eq!(status(&manager).await, (SessionState::Ready, 1));
+  }
+
+  #[tokio::test]
+  async fn a_hung_setup_replay_is_aborted_with_its_request() {
+    let backend = MockBackend::new();
+    let manager = SessionManager::with_backend(backend.clone());
+    let id = manager.create_session_with(&Caller::Admin, setup("f[x_] := x^2")).await.unwrap();
+
+    let backend = backend.on("f[x_]", MockReply::output("Null").delayed(Duration::from_secs(60)));
+    backend.kill_kernels();
+    let err = manager
+      .eval(&id, "1+1", &EvalOptions::default(), Duration::from_millis(50))
+      .await
+      .unwrap_err();
+    assert!(err.to_string().contains("replaying the setup code failed: it was aborted"), "{err}");
+    assert_eq!(status(&manager).await, (SessionState::Dead, 0));
+
+    // The worker is free for the next request.
+    tokio::time::timeout(TIMEOUT, manager.close_session(&id)).await.unwrap().unwrap();
+  }
+
+  #[tokio::test]
+  async fn failing_setup_code_fails_the_create() {
+    let backend = MockBackend::new().on(
+      "Needs",
+      MockReply::output("$Failed").with_message("Needs", "nocont", "no such package", &[])
+    );
+    let manager = SessionManager::with_backend(backend.clone());
+    let err =
+      manager.create_session_with(&Caller::Admin, setup("Needs[\"Nope`\"]")).await.unwrap_err();
+    assert!(err.to_string().contains("setup code failed: Needs::nocont: no such package"), "{err}");
+    assert!(manager.list_sessions().await.is_empty());
+
+    // Setup code that starts failing is reported when it is replayed.
+    let id = manager.create_session_with(&Caller::Admin, setup("Get[\"init.m\"]")).await.unwrap();
+    let backend = backend.on("Get", MockReply::output("$Failed"));
+    backend.kill_kernels();
+    let err = manager.eval(&id, "1+1", &EvalOptions::default(), TIMEOUT).await.unwrap_err();
+    assert!(
+      err.to_string().contains("replaying the setup code failed: it returned $Failed"),
+      "{err}"
+    );
+    assert_eq!(status(&manager).await, (SessionState::Dead, 0));
+  }
+
+  #[tokio::test]
+  async fn the_history_keeps_only_the_latest_evaluations() {
+    let manager = SessionManager::with_options(MockBackend::new(), SessionOptions {
+      history_limit: 2,
+      ..SessionOptions::default()
+    });
+    let id = manager.create_session().await.unwrap();
+    for code in ["1", "2", "3"] {
+      manager.eval(&id, code, &EvalOptions::default(), TIMEOUT).await.unwrap();
+    }
+
+    let history = manager.history(&id).await.unwrap();
+    assert_eq!(history.recorded(), 3);
+    let kept: Vec<_> = history.entries().into_iter().map(|e| e.n).collect();
+    assert_eq!(kept, vec![2, 3]);
+    assert_eq!(history.get(-1).unwrap().code, "3");
+    assert_eq!(history.get(2).unwrap().code, "2");
+    let err = history.get(1).unwrap_err();
+    assert!(err.to_string().contains("evaluation 1 was dropped"), "{err}");
+    let err = history.get(-3).unwrap_err();
+    assert!(err.to_string().contains("evaluation 1 was dropped"), "{err}");
+    let err = history.get(4).unwrap_err();
+    assert!(err.to_string().contains("no evaluation 4"), "{err}");
+  }
+
+  #[tokio::test]
+  async fn create_requests_over_the_cap_wait_in_line_or_fail() {
+    let manager = SessionManager::with_options(MockBackend::new(), SessionOptions {
+      max_sessions: Some(1),
+      ..SessionOptions::default()
+    });
+    let first = manager.create_session().await.unwrap();
+    let err = manager.create_session().await.unwrap_err();
+    assert!(
+      matches!(err, CreateError::Capacity {
+        max_sessions: 1,
+        ..
+      }),
+      "{err}"
+    );
+
+    let queued = {
+      let manager = manager.clone();
+      tokio::spawn(async move {
+        manager
+          .create_session_with(&Caller::Admin, CreateOptions {
+            queue_timeout: Some(TIMEOUT),
+            ..CreateOptions::default()
+          })
+          .await
+      })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!queued.is_finished());
+
+    manager.close_session(&first).await.unwrap();
+    let second = queued.await.unwrap().unwrap();
+    let open: Vec<_> = manager.list_sessions().await.into_iter().map(|s| s.session_id).collect();
+    assert_eq!(open, vec![second]);
+  }
+
+  #[tokio::test]
+  async fn kernel_errors_are_returned_to_the_caller() {
+    let backend = MockBackend::new().on("boom", MockReply::failure("link died"));
+    let manager = SessionManager::with_backend(backend);
+    let id = manager.create_session().await.unwrap();
+
+    let err = manager.eval(&id, "boom", &EvalOptions::default(), TIMEOUT).await.unwrap_err();
+    assert!(err.to_string().contains("link died"), "{err}");
+    // The session survives a failed evaluation.
+    assert_eq!(manager.eval(&id, "x", &EvalOptions::default(), TIMEOUT).await.unwrap().output, "x");
+  }
+
+  #[tokio::test]
+  async fn timeout_aborts_evaluation_and_session_recovers() {
+    let backend =
+      MockBackend::new().on("slow", MockReply::output("done").delayed(Duration::from_secs(60)));
+    let manager = SessionManager::with_backend(backend);
+    let id = manager.create_session().await.unwrap();
+
+    let res =
+      manager.eval(&id, "slow", &EvalOptions::default(), Duration::from_millis(50)).await.unwrap();
+    assert!(res.aborted);
+    assert_eq!(res.output, "$Aborted");
+
+    // The abort flag must not leak into the next evaluation.
+    let res = manager.eval(&id, "fast", &EvalOptions::default(), TIMEOUT).await.unwrap();
+    assert!(!res.aborted);
+    assert_eq!(res.output, "fast");
+  }
+
+  #[tokio::test]
+  async fn cancelling_a_queued_job_leaves_the_running_one_alone() {
+    let backend = MockBackend::new()
+      .on("slow", MockReply::output("slow done").delayed(Duration::from_millis(300)))
+      .on("queued", MockReply::output("never").delayed(Duration::from_secs(60)));
+    let manager = SessionManager::with_backend(backend);
+    let id = manager.create_session().await.unwrap();
+    let submit = |code| manager.submit(&Caller::Admin, &id, code, EvalOptions::default(), TIMEOUT);
+
+    let running = submit("slow").await.unwrap();
+    let queued = submit("queued").await.unwrap();
+    assert_eq!(manager.job(&Caller::Admin, &running).unwrap().state, JobState::Running);
+    assert_eq!(manager.cancel_job(&Caller::Admin, &queued).unwrap().state, JobState::Cancelled);
+
+    let finished = |job_id: &str| {
+      let job = manager.job(&Caller::Admin, job_id).unwrap();
+      job.result.is_some().then_some(job)
+    };
+    let deadline = std::time::Instant::now() + TIMEOUT;
+    while finished(&queued).is_none() && std::time::Instant::now() < deadline {
+      tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let job = finished(&running).expect("the running job finished first");
+    assert_eq!(job.state, JobState::Completed);
+    let res = job.result.unwrap().unwrap();
+    assert!(!res.aborted, "only the cancelled job is aborted");
+    assert_eq!(res.output, "slow done");
+    let job = finished(&queued).expect("the cancelled job finished");
+    assert_eq!(job.state, JobState::Cancelled);
+    assert!(job.result.unwrap().unwrap().aborted);
+
+    let other = Caller::Client("someone else".to_string());
+    assert!(manager.job(&other, &running).is_err());
+    assert!(manager.cancel_job(&other, &running).is_err());
+
+    manager.jobs.prune(Duration::ZERO);
+    let err = manager.job(&Caller::Admin, &running).unwrap_err().to_string();
+    assert!(err.contains("not found or expired"), "{err}");
+  }
+
+  #[tokio::test]
+  async fn call_graphics_options_override_session_defaults() {
+    let backend = MockBackend::new()
+      .on("Plot", MockReply::output("-Graphics-").with_graphics(&[], "Graphics", "x"));
+    let manager = SessionManager::with_backend(backend);
+    let id = manager
+      .create_session_with(&Caller::Admin, CreateOptions {
+        graphics: GraphicsOptions {
+          format: Some(GraphicsFormat::Svg),
+          ..GraphicsOptions::default()
+        },
+        ..CreateOptions::default()
+      })
+      .await
+      .unwrap();
+
+    let res = manager.eval(&id, "Plot", &EvalOptions::default(), TIMEOUT).await.unwrap();
+    assert_eq!(res.graphics[0].format, GraphicsFormat::Svg);
+
+    let options = EvalOptions {
+      graphics: GraphicsOptions {
+        format: Some(GraphicsFormat::Pdf),
+        ..GraphicsOptions::default()
+      },
+      ..EvalOptions::default()
+    };
+    let res = manager.eval(&id, "Plot", &options, TIMEOUT).await.unwrap();
+    assert_eq!(res.graphics[0].format, GraphicsFormat::Pdf);
+  }
+
+  #[tokio::test]
+  async fn invalid_graphics_options_are_rejected() {
+    let backend = MockBackend::new();
+    let manager = SessionManager::with_backend(backend.clone());
+    let bad = GraphicsOptions {
+      background: Some("Red]; Quit[".to_string()),
+      ..GraphicsOptions::default()
+    };
+    let err = manager
+      .create_session_with(&Caller::Admin, CreateOptions {
+        graphics: bad.clone(),
+        ..CreateOptions::default()
+      })
+      .await
+      .unwrap_err();
+    assert!(err.to_string().contains("invalid background color"), "{err}");
+    assert_eq!(backend.launches(), 0);
+
+    let id = manager.create_session().await.unwrap();
+    let options = EvalOptions {
+      graphics: bad,
+      ..EvalOptions::default()
+    };
+    assert!(manager.eval(&id, "1", &options, TIMEOUT).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn input_requests_are_answered_through_the_channel() {
+    let backend =
+      MockBackend::new().on("InputString", MockReply::input(InputKind::String, "name? "));
+    let manager = SessionManager::with_backend(backend);
+    let id = manager.create_session().await.unwrap();
+
+    let (input, requests) = InputChannel::new();
+    let answerer = tokio::spawn(async move {
+      let pending = requests.recv_async().await.unwrap();
+      let _ = pending.reply.send(Ok("Ada".to_string()));
+      pending.request
+    });
+    let options = EvalOptions {
+      input: Some(input),
+      ..EvalOptions::default()
+    };
+    let res = manager.eval(&id, "InputString[\"name? \"]", &options, TIMEOUT).await.unwrap();
+    assert_eq!(res.output, "Ada");
+    assert_eq!(answerer.await.unwrap(), InputRequest {
+      kind:   InputKind::String,
+      prompt: "name? ".to_string()
+    });
+  }
+
+  #[tokio::test]
+  async fn unanswerable_input_fails_cleanly() {
+    let backend = MockBackend::new().on("Input", MockReply::input(InputKind::Expression, "x? "));
+    let manager = SessionManager::with_backend(backend);
+    let id = manager.create_session().await.unwrap();
+
+    let err = manager.eval(&id, "Input[]", &EvalOptions::default(), TIMEOUT).await.unwrap_err();
+    assert!(err.to_string().contains("asked for input"), "{err}");
+
+    let (input, requests) = InputChannel::new();
+    tokio::spawn(async move {
+      while let Ok(pending) = requests.recv_async().await {
+        let _ = pending.reply.send(Err(anyhow!("the user declined to answer")));
+      }
+    });
+    let options = EvalOptions {
+      input: Some(input),
+      ..EvalOptions::default()
+    };
+    let err = manager.eval(&id, "Input[]", &options, TIMEOUT).await.unwrap_err();
+    assert!(err.to_string().contains("declined"), "{err}");
+
+    assert_eq!(manager.eval(&id, "x", &EvalOptions::default(), TIMEOUT).await.unwrap().output, "x");
+  }
+
+  #[tokio::test]
+  async fn timeout_aborts_an_evaluation_waiting_for_input() {
+    let backend = MockBackend::new().on("Input", MockReply::input(InputKind::Expression, "x? "));
+    let manager = SessionManager::with_backend(backend);
+    let id = manager.create_session().await.unwrap();
+
+    // Nobody ever answers.
+    let (input, _requests) = InputChannel::new();
+    let options = EvalOptions {
+      input: Some(input),
+      ..EvalOptions::default()
+    };
+    let res = manager.eval(&id, "Input[]", &options, Duration::from_millis(50)).await.unwrap();
+    assert!(res.aborted);
+  }
+
+  #[tokio::test]
+  async fn clients_only_see_and_use_their_own_sessions() {
+    let manager = SessionManager::with_backend(MockBackend::new());
+    let alice = Caller::Client("alice".to_string());
+    let bob = Caller::Client("bob".to_string());
+    let a = manager.create_session_with(&alice, CreateOptions::default()).await.unwrap();
+    let b = manager.create_session_with(&bob, CreateOptions::default()).await.unwrap();
+
+    manager.authorize(&a, &alice).await.unwrap();
+    let err = manager.authorize(&a, &bob).await.unwrap_err();
+    assert!(err.to_string().contains("not found"), "{err}");
+    manager.authorize(&a, &Caller::Admin).await.unwrap();
+
+    let ids = |list: Vec<SessionInfo>| list.into_iter().map(|s| s.session_id).collect::<Vec<_>>();
+    assert_eq!(ids(manager.list_sessions_for(&alice).await), vec![a.clone()]);
+    assert_eq!(ids(manager.list_sessions_for(&bob).await), vec![b.clone()]);
+    assert_eq!(manager.list_sessions_for(&Caller::Admin).await.len(), 2);
+
+    manager.close_client_sessions("alice").await;
+    assert_eq!(ids(manager.list_sessions().await), vec![b]);
+  }
+
+  #[tokio::test]
+  async fn idle_sessions_are_closed() {
+    let manager = SessionManager::with_backend(MockBackend::new());
+    let idle = manager.create_session().await.unwrap();
+    let busy = manager.create_session().await.unwrap();
+
+    {
+      let map = manager.inner.lock().await;
+      let an_hour_ago = Utc::now().timestamp() - 3600;
+      map[&idle].last_accessed.store(an_hour_ago, Ordering::SeqCst);
+    }
+
+    manager.close_idle_sessions(Duration::from_secs(1800)).await;
+
+    let remaining: Vec<String> =
+      manager.list_sessions().await.into_iter().map(|s| s.session_id).collect();
+    assert_eq!(remaining, vec![busy]);
+  }
+}
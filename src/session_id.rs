@@ -17,59 +17,144 @@
 //!   bits).
 //! - On verify: decode words -> recover payload32 + checksum12 -> recompute
 //!   checksum12 -> compare.
+//! - The secret key comes from `MATHEMATICA_MCP_SESSION_KEY`. Ids signed with
+//!   `MATHEMATICA_MCP_SESSION_KEY_PREVIOUS` still verify, so the key can be
+//!   rotated. Without a key, a random per-process key is used.
+//! - 12 bits means a guessed id verifies about 1 time in 4096: enough to reject
+//!   typos and forged ids, not a substitute for access control.
 //!
 //! This mirrors the intent of Python
 //! `AnimalIdGenerator.generate()` and
 //! `.verify()` without needing to
 //! append a signature string.
 
+use std::{
+  env,
+  fmt
+};
+
+use hmac::{
+  Hmac,
+  Mac
+};
 use rand::TryRngCore;
+use sha2::Sha256;
 
 pub type SessionIdSigner = AnimalIdGenerator;
 
-#[derive(Clone, Debug, Default)]
-pub struct AnimalIdGenerator {}
+/// Env var holding the secret key ids are signed with.
+pub const KEY_ENV: &str = "MATHEMATICA_MCP_SESSION_KEY";
+/// Env var holding the key in use before the last rotation. Ids signed with it
+/// still verify, so rotating the key does not orphan live sessions.
+pub const PREVIOUS_KEY_ENV: &str = "MATHEMATICA_MCP_SESSION_KEY_PREVIOUS";
+
+const CHECKSUM_BITS: u32 = 12;
+const WORD_BITS: u32 = 11;
+const WORDS: usize = 4;
+
+#[derive(Clone)]
+pub struct AnimalIdGenerator {
+  key:          Vec<u8>,
+  previous_key: Option<Vec<u8>>
+}
+
+impl fmt::Debug for AnimalIdGenerator {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>
+  ) -> fmt::Result {
+    f.debug_struct("AnimalIdGenerator")
+      .field("key", &"<redacted>")
+      .field("previous_key", &self.previous_key.as_ref().map(|_| "<redacted>"))
+      .finish()
+  }
+}
+
+/// A random per-process key: ids are unforgeable but die with the process.
+impl Default for AnimalIdGenerator {
+  fn default() -> Self {
+    let mut key = vec![0u8; 32];
+    rand::rngs::OsRng.try_fill_bytes(&mut key).expect("os rng should be available");
+    Self {
+      key,
+      previous_key: None
+    }
+  }
+}
 
 impl AnimalIdGenerator {
+  pub fn with_keys(
+    key: impl Into<Vec<u8>>,
+    previous_key: Option<Vec<u8>>
+  ) -> Self {
+    Self {
+      key: key.into(),
+      previous_key
+    }
+  }
+
+  /// Read the key from [`KEY_ENV`] (and [`PREVIOUS_KEY_ENV`]), falling back
+  /// to a random per-process key.
   pub fn from_env() -> Self {
-    Self {}
+    let read = |name: &str| env::var(name).ok().filter(|k| !k.is_empty()).map(String::into_bytes);
+    match read(KEY_ENV) {
+      | Some(key) => Self::with_keys(key, read(PREVIOUS_KEY_ENV)),
+      | None => {
+        tracing::debug!("{KEY_ENV} not set; signing session ids with a random per-process key");
+        Self::default()
+      }
+    }
   }
 
   /// Generate a new session id.
   pub fn generate(&self) -> String {
-    let mut rng = rand::rngs::OsRng;
-    let mut words = [String::new(), String::new(), String::new(), String::new()];
-
-    for word in &mut words {
-      let mut buf = [0u8; 2];
-      rng.try_fill_bytes(&mut buf).expect("os rng should be available");
-      let idx = (u16::from_be_bytes(buf) & 0x7ff) as u16; // 0..2047
-      *word = index_to_word(idx);
-    }
+    let mut buf = [0u8; 4];
+    rand::rngs::OsRng.try_fill_bytes(&mut buf).expect("os rng should be available");
+    let payload = u32::from_be_bytes(buf);
+    let bits = (u64::from(payload) << CHECKSUM_BITS) | u64::from(checksum(&self.key, payload));
 
-    words.join("-")
+    (0..WORDS)
+      .rev()
+      .map(|i| index_to_word(((bits >> (i as u32 * WORD_BITS)) & 0x7ff) as u16))
+      .collect::<Vec<_>>()
+      .join("-")
   }
 
-  /// Verify that a session id is well-formed.
+  /// Verify that a session id is well-formed and was signed with the current
+  /// or the previous key.
   pub fn verify(
     &self,
     session_id: &str
   ) -> bool {
     let parts: Vec<&str> = session_id.split('-').collect();
-    if parts.len() != 4 {
+    if parts.len() != WORDS {
       return false;
     }
 
+    let mut bits = 0u64;
     for part in parts {
-      if word_to_index(part).is_none() {
+      let Some(idx) = word_to_index(part) else {
         return false;
-      }
+      };
+      bits = (bits << WORD_BITS) | u64::from(idx);
     }
 
-    true
+    let payload = (bits >> CHECKSUM_BITS) as u32;
+    let check = (bits & ((1 << CHECKSUM_BITS) - 1)) as u16;
+    std::iter::once(&self.key).chain(&self.previous_key).any(|key| checksum(key, payload) == check)
   }
 }
 
+/// Top 12 bits of `HMAC-SHA256(key, payload)`.
+fn checksum(
+  key: &[u8],
+  payload: u32
+) -> u16 {
+  let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+  mac.update(&payload.to_be_bytes());
+  let digest = mac.finalize().into_bytes();
+  u16::from_be_bytes([digest[0], digest[1]]) >> (16 - CHECKSUM_BITS)
+}
 
 /// We need a 2048-entry "wordlist"
 /// (because each word encodes 11 bits).
@@ -139,6 +224,33 @@ mod tests {
     assert!(!g.verify("alert_fox-ancient_wolf-brave_bear")); // 3 words
   }
 
+  #[test]
+  fn forged_ids_fail() {
+    let g = AnimalIdGenerator::with_keys("secret", None);
+    let id = g.generate();
+    let (payload, last) = id.rsplit_once('-').unwrap();
+    let last = word_to_index(last).unwrap();
+
+    // The last word carries only checksum bits, so exactly one choice of it
+    // is valid for a given payload.
+    for idx in (0..2048).filter(|&i| i != last) {
+      let forged = format!("{payload}-{}", index_to_word(idx));
+      assert!(!g.verify(&forged), "forged id verified: {forged}");
+    }
+    assert!(!AnimalIdGenerator::with_keys("other", None).verify(&id));
+  }
+
+  #[test]
+  fn previous_key_still_verifies_after_rotation() {
+    let old = AnimalIdGenerator::with_keys("old", None);
+    let id = old.generate();
+
+    let rotated = AnimalIdGenerator::with_keys("new", Some(b"old".to_vec()));
+    assert!(rotated.verify(&id));
+    assert!(rotated.verify(&rotated.generate()));
+    assert!(!AnimalIdGenerator::with_keys("new", None).verify(&id));
+  }
+
   #[test]
   fn unknown_word_fails() {
     let g = AnimalIdGenerator::default();
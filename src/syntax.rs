@@ -0,0 +1,101 @@
+//! Syntax checking without evaluation.
+//!
+//! `evaluate` splices user code into its wrapper, so code that does not parse
+//! fails with an error about the wrapper. Checking it first with the kernel's
+//! own parser, through `SyntaxQ` and `SyntaxLength`, finds where the user's
+//! code goes wrong instead.
+
+use anyhow::anyhow;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::backend::KernelBackend;
+use crate::session::SessionManager;
+use crate::wolfram::{
+  EvalOptions,
+  OutputFormat,
+  wl_string
+};
+
+/// Where code stops parsing.
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct SyntaxError {
+  /// Characters before the error, from 0.
+  pub offset:     usize,
+  /// Line of the error, from 1.
+  pub line:       usize,
+  /// Column of the error on its line, from 1.
+  pub column:     usize,
+  /// The line holding the error, with a `^` under it on the next line.
+  pub snippet:    String,
+  /// The code is fine as far as it goes but ends early, e.g. in an
+  /// unclosed bracket or after an infix operator.
+  pub incomplete: bool
+}
+
+/// Parse `code` in the session's kernel without evaluating it. `None` means
+/// the code is syntactically valid.
+pub async fn check<B: KernelBackend>(
+  sessions: &SessionManager<B>,
+  session_id: &str,
+  code: &str
+) -> anyhow::Result<Option<SyntaxError>> {
+  let check = format!("With[{{s = \"{}\"}}, If[SyntaxQ[s], -1, SyntaxLength[s]]]", wl_string(code));
+  let options = EvalOptions {
+    output_formats: vec![OutputFormat::OutputForm],
+    ..EvalOptions::default()
+  };
+  let res = sessions.eval_unrecorded(session_id, &check, &options, sessions.eval_timeout()).await?;
+  let length: i64 = res
+    .output
+    .trim()
+    .parse()
+    .map_err(|_| anyhow!("unexpected syntax check result from kernel: {}", res.output))?;
+  Ok(usize::try_from(length).ok().map(|length| locate(code, length)))
+}
+
+/// The error in `code` given its `SyntaxLength`, which runs past the end of
+/// the code when the code is incomplete.
+fn locate(
+  code: &str,
+  length: usize
+) -> SyntaxError {
+  let total = code.chars().count();
+  let offset = length.min(total);
+  let before: String = code.chars().take(offset).collect();
+  let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+  let column = before[line_start..].chars().count() + 1;
+  let line_text = code[line_start..].lines().next().unwrap_or_default();
+  SyntaxError {
+    offset,
+    line: before.matches('\n').count() + 1,
+    column,
+    snippet: format!("{line_text}\n{}^", " ".repeat(column - 1)),
+    incomplete: length > total
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn errors_are_located_by_line_and_column() {
+    // SyntaxLength["x = 1;\nf[2]]"] is 11: the second `]` does not parse.
+    assert_eq!(locate("x = 1;\nf[2]]", 11), SyntaxError {
+      offset:     11,
+      line:       2,
+      column:     5,
+      snippet:    "f[2]]\n    ^".to_string(),
+      incomplete: false
+    });
+    // SyntaxLength["Plot[x,"] is 8: the code needs to go on.
+    assert_eq!(locate("Plot[x,", 8), SyntaxError {
+      offset:     7,
+      line:       1,
+      column:     8,
+      snippet:    "Plot[x,\n       ^".to_string(),
+      incomplete: true
+    });
+  }
+}
@@ -1,5 +1,21 @@
+use std::io::Read;
+use std::ops::ControlFlow;
 use std::path::PathBuf;
-use std::env;
+use std::sync::atomic::{
+  AtomicBool,
+  Ordering
+};
+use std::sync::{
+  Arc,
+  Mutex
+};
+use std::time::Duration;
+use std::{
+  env,
+  fmt,
+  process,
+  thread
+};
 
 use anyhow::{
   Context,
@@ -10,19 +26,28 @@ use wolfram_expr::{
   Expr,
   ExprKind
 };
-use wstp::Link;
-use wstp::kernel::WolframKernelProcess;
+use wstp::{
+  Link,
+  Protocol,
+  UrgentMessage
+};
 
+use crate::expr_tree::{
+  self,
+  ExprTree
+};
 use crate::platform;
 
-pub fn resolve_kernel_cmd() -> anyhow::Result<String> {
-  // 1) honor WOLFRAM_KERNEL_PATH
-  if let Ok(raw) = env::var("WOLFRAM_KERNEL_PATH") {
+/// Find the kernel executable. `configured` is the `kernel.path` setting,
+/// which `WOLFRAM_KERNEL_PATH` also feeds; see `config`.
+pub fn resolve_kernel_cmd(configured: Option<&str>) -> anyhow::Result<String> {
+  // 1) honor the configured path
+  if let Some(raw) = configured {
     let raw = raw.trim();
     if !raw.is_empty() {
       let path = platform::shellexpand_path(raw)?;
       platform::validate_executable(&path)?;
-      tracing::info!(kernel_path = %path.display(), "using kernel from WOLFRAM_KERNEL_PATH");
+      tracing::info!(kernel_path = %path.display(), "using configured kernel path");
       return Ok(path.to_string_lossy().to_string());
     }
   }
@@ -44,92 +69,1078 @@ pub fn resolve_kernel_cmd() -> anyhow::Result<String> {
   // 4) last resort: try bare WolframKernel and let OS/WSTP resolve (may work on
   //    some setups)
   tracing::warn!(
-    "no kernel found via WOLFRAM_KERNEL_PATH, discovery, or PATH; trying 'WolframKernel' as a fallback"
+    "no kernel found via kernel.path, discovery, or PATH; trying 'WolframKernel' as a fallback"
   );
   Ok("WolframKernel".to_string())
 }
 
-pub fn launch_link(kernel_cmd: &str) -> anyhow::Result<WolframKernelProcess> {
+/// A kernel child process and the link to it. This is
+/// `wstp::kernel::WolframKernelProcess`, which cannot take extra command-line
+/// arguments.
+pub struct KernelProcess {
+  process: process::Child,
+  link:    Link
+}
+
+impl KernelProcess {
+  pub fn link(&mut self) -> &mut Link {
+    &mut self.link
+  }
+
+  /// Whether the kernel process is still running.
+  pub fn is_alive(&mut self) -> bool {
+    matches!(self.process.try_wait(), Ok(None))
+  }
+}
+
+/// The link to the kernel is gone: the kernel crashed, exited or was killed,
+/// and only a new kernel can carry on.
+#[derive(Debug)]
+pub struct LinkDead {
+  /// The WSTP operation that failed, and how.
+  pub detail: String
+}
+
+impl fmt::Display for LinkDead {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>
+  ) -> fmt::Result {
+    write!(f, "the link to the kernel died: {}", self.detail)
+  }
+}
+
+impl std::error::Error for LinkDead {}
+
+/// The code does not parse, so it was not spliced into the wrapper, let alone
+/// evaluated. [`syntax::check`](crate::syntax::check) finds where.
+#[derive(Debug)]
+pub struct InvalidSyntax;
+
+impl fmt::Display for InvalidSyntax {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>
+  ) -> fmt::Result {
+    write!(f, "the code has a syntax error")
+  }
+}
+
+impl std::error::Error for InvalidSyntax {}
+
+/// The error for a failed WSTP operation on a kernel link: a [`LinkDead`] when
+/// the link itself is gone, a plain error otherwise.
+fn link_error(
+  what: &str,
+  e: wstp::Error
+) -> anyhow::Error {
+  let detail = format!("{what} failed: {e:?}");
+  match e.code() {
+    | Some(wstp::sys::WSEDEAD | wstp::sys::WSECLOSED | wstp::sys::WSECONNECT) => {
+      LinkDead {
+        detail
+      }
+      .into()
+    }
+    | _ => anyhow!(detail)
+  }
+}
+
+/// How often to check whether a launching kernel exited before connecting.
+const LAUNCH_POLL: Duration = Duration::from_millis(50);
+/// How much of a launching kernel's stdout and stderr to keep for error
+/// reports.
+const LAUNCH_OUTPUT_LIMIT: usize = 8 * 1024;
+
+/// The kernel could not start because no license was available: every seat is
+/// in use, or the license server refused it.
+#[derive(Debug)]
+pub struct LicenseExhausted {
+  /// What the kernel or WSTP said.
+  pub detail: String
+}
+
+impl fmt::Display for LicenseExhausted {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>
+  ) -> fmt::Result {
+    write!(f, "no Wolfram license available: {}", self.detail)
+  }
+}
+
+impl std::error::Error for LicenseExhausted {}
+
+/// The error for a kernel launch that failed with `detail`: a
+/// [`LicenseExhausted`] when `detail` reads like a license failure, a plain
+/// launch error otherwise.
+pub fn launch_error(detail: String) -> anyhow::Error {
+  let lower = detail.to_lowercase();
+  if ["license", "password", "mathlm"].iter().any(|word| lower.contains(word)) {
+    LicenseExhausted {
+      detail
+    }
+    .into()
+  } else {
+    anyhow!("WSTP launch failed: {detail}")
+  }
+}
+
+/// Launch `kernel_cmd` with `args` after the WSTP connection arguments and
+/// wait for it to connect back.
+///
+/// A kernel that cannot get a license prints why and exits without ever
+/// connecting, which would leave `activate` waiting forever. So a watcher
+/// thread connects to the link itself when the process exits early, and the
+/// launch fails with whatever the kernel printed.
+pub fn launch_link(
+  kernel_cmd: &str,
+  args: &[String]
+) -> anyhow::Result<KernelProcess> {
   let path = PathBuf::from(kernel_cmd);
-  tracing::debug!(kernel_path = %path.display(), "launching Wolfram kernel");
-  let kernel =
-    WolframKernelProcess::launch(&path).map_err(|e| anyhow!("WSTP launch failed: {e:?}"))?;
-  Ok(kernel)
+  tracing::debug!(kernel_path = %path.display(), ?args, "launching Wolfram kernel");
+  let mut link =
+    Link::listen(Protocol::SharedMemory, "").map_err(|e| launch_error(format!("{e:?}")))?;
+  let link_name = link.link_name();
+  let mut child = process::Command::new(&path)
+    .args(["-wstp", "-linkprotocol", "SharedMemory", "-linkconnect", "-linkname"])
+    .arg(&link_name)
+    .args(args)
+    .stdout(process::Stdio::piped())
+    .stderr(process::Stdio::piped())
+    .spawn()
+    .map_err(|e| anyhow!("WSTP launch failed: could not start {}: {e}", path.display()))?;
+  let output = Arc::new(Mutex::new(String::new()));
+  if let Some(stdout) = child.stdout.take() {
+    capture_output(stdout, output.clone());
+  }
+  if let Some(stderr) = child.stderr.take() {
+    capture_output(stderr, output.clone());
+  }
+
+  let activated = Arc::new(AtomicBool::new(false));
+  let watcher = {
+    let activated = activated.clone();
+    thread::spawn(move || {
+      while !activated.load(Ordering::SeqCst) {
+        match child.try_wait() {
+          | Ok(None) => thread::sleep(LAUNCH_POLL),
+          | Ok(Some(status)) => {
+            // Nothing else will connect now. Detached, since the kernel may
+            // have connected after all and nobody would accept this one.
+            thread::spawn(move || {
+              if let Ok(mut unblock) = Link::connect(Protocol::SharedMemory, &link_name) {
+                let _ = unblock.activate();
+              }
+            });
+            return (child, Some(status));
+          }
+          | Err(_) => break
+        }
+      }
+      (child, None)
+    })
+  };
+
+  let activation = link.activate();
+  activated.store(true, Ordering::SeqCst);
+  let (process, exited) =
+    watcher.join().map_err(|_| anyhow!("WSTP launch failed: kernel watcher panicked"))?;
+  if let Some(status) = exited {
+    // Let the readers catch the last of the output.
+    thread::sleep(LAUNCH_POLL);
+    let output = output.lock().unwrap_or_else(|e| e.into_inner());
+    let output = output.trim();
+    return Err(launch_error(
+      if output.is_empty() {
+        format!("the kernel exited before connecting ({status})")
+      } else {
+        format!("the kernel exited before connecting ({status}): {output}")
+      }
+    ));
+  }
+  activation.map_err(|e| launch_error(format!("{e:?}")))?;
+  Ok(KernelProcess {
+    process,
+    link
+  })
+}
+
+/// Keep reading `pipe` until it closes, so the kernel never blocks on a full
+/// pipe, and append the first [`LAUNCH_OUTPUT_LIMIT`] bytes to `output`.
+fn capture_output(
+  mut pipe: impl Read + Send + 'static,
+  output: Arc<Mutex<String>>
+) {
+  thread::spawn(move || {
+    let mut buf = [0; 1024];
+    while let Ok(n @ 1..) = pipe.read(&mut buf) {
+      let mut output = output.lock().unwrap_or_else(|e| e.into_inner());
+      if output.len() < LAUNCH_OUTPUT_LIMIT {
+        output.push_str(&String::from_utf8_lossy(&buf[..n]));
+      }
+    }
+  });
+}
+
+/// How a result is rendered to text.
+#[derive(
+  Debug,
+  serde::Serialize,
+  serde::Deserialize,
+  schemars::JsonSchema,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  Default,
+)]
+pub enum OutputFormat {
+  #[default]
+  InputForm,
+  FullForm,
+  OutputForm,
+  #[serde(alias = "TeX", alias = "LaTeX")]
+  TeXForm,
+  MathML,
+  /// `TraditionalForm`, rendered as plain text.
+  TraditionalForm,
+  StandardForm
+}
+
+impl OutputFormat {
+  /// Wolfram Language code rendering the wrapper's `res` in this format.
+  fn render_code(self) -> &'static str {
+    match self {
+      | Self::InputForm => "ToString[res, InputForm]",
+      | Self::FullForm => "ToString[FullForm[res]]",
+      | Self::OutputForm => "ToString[res, OutputForm]",
+      | Self::TeXForm => "ToString[TeXForm[res]]",
+      | Self::MathML => "ExportString[res, \"MathML\"]",
+      | Self::TraditionalForm => "ToString[TraditionalForm[res]]",
+      | Self::StandardForm => "ToString[res, StandardForm]"
+    }
+  }
+}
+
+/// File format graphics are exported in.
+#[derive(
+  Debug,
+  serde::Serialize,
+  serde::Deserialize,
+  schemars::JsonSchema,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  Default,
+)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum GraphicsFormat {
+  #[default]
+  Png,
+  Svg,
+  Pdf,
+  #[serde(alias = "JPG")]
+  Jpeg
+}
+
+impl GraphicsFormat {
+  /// Format name as `Export` knows it.
+  pub(crate) fn export_name(self) -> &'static str {
+    match self {
+      | Self::Png => "PNG",
+      | Self::Svg => "SVG",
+      | Self::Pdf => "PDF",
+      | Self::Jpeg => "JPEG"
+    }
+  }
+
+  pub fn extension(self) -> &'static str {
+    match self {
+      | Self::Png => "png",
+      | Self::Svg => "svg",
+      | Self::Pdf => "pdf",
+      | Self::Jpeg => "jpg"
+    }
+  }
+
+  pub fn mime_type(self) -> &'static str {
+    match self {
+      | Self::Png => "image/png",
+      | Self::Svg => "image/svg+xml",
+      | Self::Pdf => "application/pdf",
+      | Self::Jpeg => "image/jpeg"
+    }
+  }
+}
+
+/// `ImageSize`: a width, or a width and height, in printer's points.
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ImageSize {
+  Width(u32),
+  WidthHeight([u32; 2])
+}
+
+/// Color names accepted for `Background`. The name is spliced into the export
+/// call as a bare symbol, so anything else (`Quit`, say) could run code.
+const NAMED_COLORS: &[&str] = &[
+  "White",
+  "Black",
+  "Gray",
+  "Red",
+  "Green",
+  "Blue",
+  "Cyan",
+  "Magenta",
+  "Yellow",
+  "Brown",
+  "Orange",
+  "Pink",
+  "Purple",
+  "LightGray",
+  "LightRed",
+  "LightGreen",
+  "LightBlue",
+  "LightCyan",
+  "LightMagenta",
+  "LightYellow",
+  "LightBrown",
+  "LightOrange",
+  "LightPink",
+  "LightPurple",
+  "Transparent",
+  "None"
+];
+
+/// How graphics are exported. Every field is optional so that per-call options
+/// can be layered over per-session defaults with [`GraphicsOptions::or`].
+#[derive(
+  Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema, Clone, PartialEq, Default,
+)]
+pub struct GraphicsOptions {
+  /// PNG (default), SVG, PDF or JPEG.
+  #[serde(default)]
+  pub format:     Option<GraphicsFormat>,
+  /// `ImageResolution` in dpi, for raster formats.
+  #[serde(default)]
+  pub resolution: Option<u32>,
+  #[serde(default)]
+  pub image_size: Option<ImageSize>,
+  /// `Background`: a color name such as `White`, `None` for transparent, or
+  /// `#RRGGBB`.
+  #[serde(default)]
+  pub background: Option<String>
+}
+
+impl GraphicsOptions {
+  /// These options, with unset fields taken from `defaults`.
+  pub fn or(
+    self,
+    defaults: &GraphicsOptions
+  ) -> Self {
+    Self {
+      format:     self.format.or(defaults.format),
+      resolution: self.resolution.or(defaults.resolution),
+      image_size: self.image_size.or_else(|| defaults.image_size.clone()),
+      background: self.background.or_else(|| defaults.background.clone())
+    }
+  }
+
+  /// Arguments for `ExportByteArray` after the expression: the format name
+  /// followed by options.
+  pub fn export_args(&self) -> anyhow::Result<String> {
+    let mut args = vec![format!("\"{}\"", self.format.unwrap_or_default().export_name())];
+    if let Some(dpi) = self.resolution {
+      args.push(format!("ImageResolution -> {dpi}"));
+    }
+    match &self.image_size {
+      | Some(ImageSize::Width(w)) => args.push(format!("ImageSize -> {w}")),
+      | Some(ImageSize::WidthHeight([w, h])) => args.push(format!("ImageSize -> {{{w}, {h}}}")),
+      | None => {}
+    }
+    if let Some(background) = &self.background {
+      let color = if let Some(hex) = background.strip_prefix('#') {
+        if !(hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit())) {
+          return Err(anyhow!("invalid background color '{background}', expected #RRGGBB"));
+        }
+        format!("RGBColor[\"{background}\"]")
+      } else if NAMED_COLORS.contains(&background.as_str()) {
+        background.clone()
+      } else {
+        return Err(anyhow!(
+          "invalid background color '{background}', expected a color name such as White or #RRGGBB"
+        ));
+      };
+      args.push(format!("Background -> {color}"));
+    }
+    Ok(args.join(", "))
+  }
+}
+
+/// Per-evaluation knobs passed from the caller down to the kernel.
+#[derive(Debug, Clone)]
+pub struct EvalOptions {
+  /// Renderings to produce, in order. The first one is also returned as
+  /// `output`.
+  pub output_formats:     Vec<OutputFormat>,
+  /// Also return the result as an [`ExprTree`].
+  pub include_expression: bool,
+  /// Graphics export settings for this call; unset fields fall back to the
+  /// session's defaults.
+  pub graphics:           GraphicsOptions,
+  /// Where to ask when the code calls `Input[]` or `InputString[]`. Without
+  /// one the evaluation fails instead of waiting forever.
+  pub input:              Option<InputChannel>,
+  /// Where to report `Print` output and progress while the evaluation runs.
+  pub events:             Option<EventChannel>
+}
+
+impl Default for EvalOptions {
+  fn default() -> Self {
+    Self {
+      output_formats:     vec![OutputFormat::InputForm],
+      include_expression: false,
+      graphics:           GraphicsOptions::default(),
+      input:              None,
+      events:             None
+    }
+  }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema, Clone, PartialEq)]
+pub struct FormattedOutput {
+  pub format: OutputFormat,
+  pub text:   String
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema, Clone)]
 pub struct EvalResult {
-  pub output:   String,
-  pub logs:     Vec<String>,
-  pub graphics: Option<String> // Base64 PNG
+  pub output:     String,
+  /// The result in every requested output format; `output` repeats the first.
+  #[serde(default)]
+  pub outputs:    Vec<FormattedOutput>,
+  pub logs:       Vec<String>,
+  /// Every outermost graphics object or image in the result.
+  #[serde(default)]
+  pub graphics:   Vec<GraphicsArtifact>,
+  /// Kernel messages (`Power::infy`, ...) issued during the evaluation, kept
+  /// apart from `Print` output in `logs`.
+  #[serde(default)]
+  pub messages:   Vec<WolframMessage>,
+  /// The evaluation was aborted before it finished; `output` is `$Aborted`.
+  #[serde(default)]
+  pub aborted:    bool,
+  /// The result as a typed tree, when [`EvalOptions::include_expression`] is
+  /// set.
+  #[serde(default)]
+  pub expression: Option<ExprTree>
+}
+
+/// A graphics object or image found in a result, exported with the
+/// evaluation's [`GraphicsOptions`].
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema, Clone, PartialEq)]
+pub struct GraphicsArtifact {
+  /// Where it sits in the result, as a part specification: `[]` is the result
+  /// itself, `[2, {"key": "\"plot\""}]` is `res[[2, Key["plot"]]]`.
+  pub position: Vec<Part>,
+  /// Head of the object, e.g. `Graphics` or `Image`.
+  pub head:     String,
+  pub format:   GraphicsFormat,
+  /// Base64-encoded file contents.
+  pub data:     String
+}
+
+/// One step of a part specification.
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Part {
+  Index(i64),
+  /// An association key, in `InputForm`.
+  Key {
+    key: String
+  }
+}
+
+impl fmt::Display for Part {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>
+  ) -> fmt::Result {
+    match self {
+      | Self::Index(i) => write!(f, "{i}"),
+      | Self::Key {
+        key
+      } => write!(f, "Key[{key}]")
+    }
+  }
+}
+
+/// A message issued with `Message[sym::tag, args...]`.
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema, Clone, PartialEq)]
+pub struct WolframMessage {
+  /// Symbol the message is attached to, e.g. `Power`.
+  pub symbol:    String,
+  /// Message tag, e.g. `infy`.
+  pub tag:       String,
+  /// Formatted message text as the kernel would print it.
+  pub text:      String,
+  /// Message arguments in `InputForm`.
+  pub arguments: Vec<String>
+}
+
+impl EvalResult {
+  pub fn aborted(logs: Vec<String>) -> Self {
+    Self {
+      output: "$Aborted".to_string(),
+      outputs: Vec::new(),
+      logs,
+      graphics: Vec::new(),
+      messages: Vec::new(),
+      aborted: true,
+      expression: None
+    }
+  }
+}
+
+/// Asks a running evaluation to abort.
+///
+/// The session worker owns the link and is blocked inside [`evaluate`], so
+/// other threads cannot touch the link directly. Instead they raise this flag,
+/// and `evaluate` sends the WSTP abort message itself while it waits for the
+/// next packet.
+#[derive(Clone, Debug, Default)]
+pub struct AbortSignal(Arc<AtomicBool>);
+
+impl AbortSignal {
+  pub fn request(&self) {
+    self.0.store(true, Ordering::SeqCst);
+  }
+
+  pub fn is_requested(&self) -> bool {
+    self.0.load(Ordering::SeqCst)
+  }
+
+  pub fn reset(&self) {
+    self.0.store(false, Ordering::SeqCst);
+  }
+}
+
+/// What the kernel is asking for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+  /// `Input[]`: the answer is parsed as Wolfram Language code.
+  Expression,
+  /// `InputString[]`: the answer is taken verbatim.
+  String
 }
 
+/// A kernel request for input, raised by `Input[]` or `InputString[]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputRequest {
+  pub kind:   InputKind,
+  pub prompt: String
+}
+
+impl InputRequest {
+  /// Ask `input` for the answer, failing with an explanation when there is
+  /// nobody to ask or nobody answers.
+  pub fn ask(
+    self,
+    input: Option<&InputChannel>,
+    abort: &AbortSignal
+  ) -> anyhow::Result<String> {
+    let prompt = self.prompt.clone();
+    match input {
+      | Some(input) => input.ask(self, abort),
+      | None => Err(anyhow!("this caller cannot answer input requests"))
+    }
+    .map_err(|e| {
+      anyhow!("the code asked for input (prompt: {prompt:?}) but none was provided: {e}")
+    })
+  }
+}
+
+/// An [`InputRequest`] waiting for its answer, or for the reason there is
+/// none.
+#[derive(Debug)]
+pub struct PendingInput {
+  pub request: InputRequest,
+  pub reply:   flume::Sender<anyhow::Result<String>>
+}
+
+/// Carries kernel input requests from the session worker, which is blocked
+/// inside [`evaluate`], to whoever can answer them: the MCP client through
+/// elicitation, or the terminal in REPL mode.
+#[derive(Clone, Debug)]
+pub struct InputChannel(flume::Sender<PendingInput>);
+
+impl InputChannel {
+  pub fn new() -> (Self, flume::Receiver<PendingInput>) {
+    let (tx, rx) = flume::unbounded();
+    (Self(tx), rx)
+  }
+
+  /// Ask for an answer and block until it arrives. Gives up when `abort` is
+  /// raised, so a timeout still interrupts an evaluation waiting on input.
+  pub fn ask(
+    &self,
+    request: InputRequest,
+    abort: &AbortSignal
+  ) -> anyhow::Result<String> {
+    let (reply, answer) = flume::bounded(1);
+    self
+      .0
+      .send(PendingInput {
+        request,
+        reply
+      })
+      .map_err(|_| anyhow!("nobody is listening for input requests"))?;
+    loop {
+      match answer.recv_timeout(INPUT_POLL) {
+        | Ok(answer) => return answer,
+        | Err(flume::RecvTimeoutError::Timeout) if abort.is_requested() => {
+          return Err(anyhow!("evaluation aborted while waiting for input"));
+        }
+        | Err(flume::RecvTimeoutError::Timeout) => {}
+        | Err(flume::RecvTimeoutError::Disconnected) => {
+          return Err(anyhow!("input request was dropped without an answer"));
+        }
+      }
+    }
+  }
+}
+
+/// Something a running evaluation reports before it returns.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalEvent {
+  /// A line of `Print` output; it also ends up in [`EvalResult::logs`].
+  Print(String),
+  /// A `MathematicaMCP`Progress[done, total, message]` call.
+  Progress {
+    done:    f64,
+    total:   Option<f64>,
+    message: Option<String>
+  }
+}
+
+/// Carries [`EvalEvent`]s from the session worker as they happen, so a long
+/// evaluation can be watched before its result is in.
+#[derive(Clone, Debug)]
+pub struct EventChannel(flume::Sender<EvalEvent>);
+
+impl EventChannel {
+  pub fn new() -> (Self, flume::Receiver<EvalEvent>) {
+    let (tx, rx) = flume::unbounded();
+    (Self(tx), rx)
+  }
+
+  /// Report `event`. Nobody listening is not an error; the evaluation goes on.
+  pub fn send(
+    &self,
+    event: EvalEvent
+  ) {
+    let _ = self.0.send(event);
+  }
+}
+
+/// How often [`InputChannel::ask`] checks for an abort request.
+const INPUT_POLL: Duration = Duration::from_millis(20);
+
+/// Kernel-side message handler: records every message that is actually
+/// printed (not `Quiet`ed or `Off`) into the wrapper's local `messages` list as
+/// `MathematicaMCP`Message[symbol, tag, text, arguments...]`.
+const MESSAGE_HANDLER: &str = r#"Replace[#, Hold[Message[MessageName[s_, t_], a___], True] :> AppendTo[messages, MathematicaMCP`Message[SymbolName[Unevaluated[s]], t, ToString[StringForm[If[StringQ[MessageName[s, t]], MessageName[s, t], MessageName[General, t]], a]], Sequence @@ Map[ToString[Replace[#, HoldForm[e_] :> Defer[e]], InputForm] &, {a}]]]] &"#;
+
+/// Kernel-side graphics detection: every outermost graphics object or image in
+/// `res` (the result itself, list elements, association values, ...) as
+/// `MathematicaMCP`Graphic[Base64 data, head,
+/// MathematicaMCP`Position[part...]]`, exported with the wrapper's local
+/// `export` arguments, or with `MathematicaMCP`ExportFailed` for the data when
+/// the export fails. `GraphicsGrid`, `GraphicsRow`, `GraphicsColumn` and
+/// `Show` all evaluate to `Graphics`.
+const GRAPHICS_COLLECTOR: &str = r#"Module[{pos = Position[res, g_ /; MemberQ[{Graphics, Graphics3D, BoxData, Graph, GeoGraphics, Legended, Placed, Image, Image3D}, Head[g]], {0, Infinity}, Heads -> False]}, pos = Select[pos, Function[p, NoneTrue[pos, Length[#] < Length[p] && Take[p, Length[#]] === # &]]]; MathematicaMCP`Graphics @@ Map[MathematicaMCP`Graphic[With[{b = Quiet[ExportByteArray[Extract[res, #], Sequence @@ export]]}, If[ByteArrayQ[b], BaseEncode[b], MathematicaMCP`ExportFailed]], ToString[Head[Extract[res, #]]], MathematicaMCP`Position @@ Replace[#, Key[k_] :> MathematicaMCP`Key[ToString[k, InputForm]], {1}]] &, pos]]"#;
+
+/// Kernel-side progress reporting for user code:
+/// `MathematicaMCP`Progress[done]`, `MathematicaMCP`Progress[done, total]` or
+/// `MathematicaMCP`Progress[done, total, message]` sends a text packet holding
+/// `MathematicaMCP`ProgressReport[done, total or MathematicaMCP`None,
+/// message]` straight to the parent link, which `evaluate` reads as it
+/// arrives.
+const PROGRESS_DEFINITION: &str = r#"MathematicaMCP`Progress[done_?NumericQ, total_ : None, message_String : ""] := (LinkWrite[$ParentLink, TextPacket[MathematicaMCP`ProgressReport[N[done], If[NumericQ[total], N[total], MathematicaMCP`None], message]]];)"#;
+
 pub fn evaluate(
   link: &mut Link,
-  code: &str
+  code: &str,
+  options: &EvalOptions,
+  abort: &AbortSignal
 ) -> anyhow::Result<EvalResult> {
-  // We wrap the code in a Module that detects graphics and returns a List:
-  // {ToString[res, InputForm], If[graphicsQ[res],
-  // Base64Encode[ExportByteArray[res, "PNG"]], Null]} where graphicsQ checks
-  // for common graphics heads.
+  // We wrap the code in a Module that detects graphics, collects messages
+  // through a "Message" handler, and returns
+  // MathematicaMCP`Result[MathematicaMCP`Outputs[rendering per requested
+  // format], MathematicaMCP`Graphics[...], MathematicaMCP`Messages[...],
+  // tree or MathematicaMCP`None]. Only marker heads, strings and machine
+  // numbers cross the link, so `get_expr` can read it without resolving
+  // contexts; see `expr_tree`.
+
+  let formats = if options.output_formats.is_empty() {
+    vec![OutputFormat::InputForm]
+  } else {
+    options.output_formats.clone()
+  };
+  let renderings = formats.iter().map(|f| f.render_code()).collect::<Vec<_>>().join(", ");
+  let (encoder, tree) = if options.include_expression {
+    (expr_tree::ENCODER, "With[{r = res}, tree[r]]")
+  } else {
+    ("Null", "MathematicaMCP`None")
+  };
+  let export = options.graphics.export_args()?;
 
   let wrapper = format!(
-    "ExportString[ Module[{{res, graphics}}, res = Check[{code}, $Failed]; graphics = \
-     Replace[res, {{ g_ /; MemberQ[{{Graphics, Graphics3D, BoxData, Graph, GeoGraphics, Legended, \
-     Placed}}, Head[g]] :> ExportString[g, \"PNG\"], _ :> Null }}]; <|\"output\" -> ToString[res, \
-     InputForm], \"graphics\" -> graphics|> ], \"JSON\" ]"
+    "{PROGRESS_DEFINITION}; CheckAbort[ Module[{{res, graphics, messages = {{}}, tree, export = \
+     {{{export}}}}}, Internal`HandlerBlock[{{\"Message\", {MESSAGE_HANDLER}}}, res = \
+     Check[{code}, $Failed]]; graphics = {GRAPHICS_COLLECTOR}; {encoder}; \
+     MathematicaMCP`Result[MathematicaMCP`Outputs[{renderings}], graphics, \
+     MathematicaMCP`Messages @@ messages, {tree}] ], MathematicaMCP`Aborted ]"
   );
 
-  link
-    .put_eval_packet(&Expr::normal(wolfram_expr::Symbol::new("System`ToExpression"), vec![
-      Expr::string(&wrapper),
-    ]))
-    .map_err(|e| anyhow!("put_eval_packet failed: {e:?}"))?;
+  // Code that does not parse would make `ToExpression` return a bare
+  // `$Failed`, which `get_expr` cannot read, so it is checked on its own first.
+  let symbol = |name| wolfram_expr::Symbol::new(name);
+  let guarded = Expr::normal(symbol("System`If"), vec![
+    Expr::normal(symbol("System`SyntaxQ"), vec![Expr::string(code)]),
+    Expr::normal(symbol("System`ToExpression"), vec![Expr::string(&wrapper)]),
+    Expr::symbol(symbol("MathematicaMCP`SyntaxError")),
+  ]);
 
-  link.flush().map_err(|e| anyhow!("flush failed: {e:?}"))?;
+  // `put_eval_packet` would flatten the WSTP error code into a string, and
+  // `link_error` needs it.
+  link.put_function("System`EvaluatePacket", 1).map_err(|e| link_error("put_eval_packet", e))?;
+  link.put_expr(&guarded).map_err(|e| link_error("put_eval_packet", e))?;
+  link.end_packet().map_err(|e| link_error("put_eval_packet", e))?;
+
+  link.flush().map_err(|e| link_error("flush", e))?;
 
   let mut logs = Vec::new();
+  let mut in_message = false;
+  let mut abort_sent = false;
+  let mut input_failure = None;
   loop {
-    let pkt = link.raw_next_packet().map_err(|e| anyhow!("raw_next_packet failed: {e:?}"))?;
+    wait_for_packet(link, abort, &mut abort_sent)?;
+    let pkt = link.raw_next_packet().map_err(|e| link_error("raw_next_packet", e))?;
 
     match pkt {
       | wstp::sys::RETURNPKT => {
-        let result_expr = link.get_expr().map_err(|e| anyhow!("get_expr failed: {e:?}"))?;
-        link.new_packet().map_err(|e| anyhow!("new_packet failed: {e:?}"))?;
-
-        let json_str = match result_expr.kind() {
-          | ExprKind::String(s) => s.clone(),
-          | _ => return Err(anyhow!("expected JSON string from kernel, got: {result_expr:?}"))
-        };
-
-        let val: serde_json::Value = serde_json::from_str(&json_str)?;
-        let output = val["output"].as_str().unwrap_or("").to_string();
-        let graphics = val["graphics"].as_str().map(|s| s.to_string());
+        let result_expr = link.get_expr().map_err(|e| link_error("get_expr", e))?;
+        link.new_packet().map_err(|e| link_error("new_packet", e))?;
+        // The abort crossed a result already on its way, so the kernel still
+        // holds it and would abort the next evaluation instead.
+        if abort_sent && !expr_tree::is_marker(&result_expr, "Aborted") {
+          drain_abort(link)?;
+        }
 
-        return Ok(EvalResult {
-          output,
-          logs,
-          graphics
-        });
+        if let Some(failure) = input_failure {
+          return Err(failure);
+        }
+        // An abort unwinds the whole wrapper into the CheckAbort fallback.
+        if expr_tree::is_marker(&result_expr, "Aborted") {
+          return Ok(EvalResult::aborted(logs));
+        }
+        if expr_tree::is_marker(&result_expr, "SyntaxError") {
+          return Err(InvalidSyntax.into());
+        }
+        let graphics_format = options.graphics.format.unwrap_or_default();
+        return decode_result(&result_expr, &formats, graphics_format, logs);
       }
       | wstp::sys::TEXTPKT => {
-        if let Ok(expr) = link.get_expr() {
-          if let ExprKind::String(s) = expr.kind() {
-            logs.push(s.clone());
+        // The text following a MESSAGEPKT is the rendered message, which the
+        // handler already captured in structured form.
+        if !std::mem::take(&mut in_message)
+          && let Ok(expr) = link.get_expr()
+        {
+          let event = match expr.kind() {
+            | ExprKind::String(s) => {
+              logs.push(s.clone());
+              Some(EvalEvent::Print(s.clone()))
+            }
+            | _ => decode_progress(&expr)
+          };
+          if let (Some(events), Some(event)) = (&options.events, event) {
+            events.send(event);
           }
         }
-        link.new_packet().map_err(|e| anyhow!("new_packet failed: {e:?}"))?;
+        link.new_packet().map_err(|e| link_error("new_packet", e))?;
       }
       | wstp::sys::MESSAGEPKT => {
-        link.new_packet().map_err(|e| anyhow!("new_packet failed: {e:?}"))?;
+        in_message = true;
+        link.new_packet().map_err(|e| link_error("new_packet", e))?;
+      }
+      | pkt @ (wstp::sys::INPUTPKT | wstp::sys::INPUTSTRPKT) => {
+        let prompt = match link.get_expr() {
+          | Ok(expr) => {
+            match expr.kind() {
+              | ExprKind::String(s) => s.clone(),
+              | _ => expr.to_string()
+            }
+          }
+          | Err(_) => String::new()
+        };
+        link.new_packet().map_err(|e| link_error("new_packet", e))?;
+        let request = InputRequest {
+          kind: if pkt == wstp::sys::INPUTPKT {
+            InputKind::Expression
+          } else {
+            InputKind::String
+          },
+          prompt
+        };
+        answer_input(
+          link,
+          request,
+          options.input.as_ref(),
+          abort,
+          &mut abort_sent,
+          &mut input_failure
+        )?;
       }
       | _ => {
-        link.new_packet().map_err(|e| anyhow!("new_packet failed: {e:?}"))?;
+        link.new_packet().map_err(|e| link_error("new_packet", e))?;
+      }
+    }
+  }
+}
+
+/// Reply to an `Input[]` or `InputString[]` request. The kernel blocks until
+/// it reads an `EnterTextPacket`, so it always gets one; when there is no
+/// answer, the evaluation is aborted first and the reason is kept in
+/// `input_failure` to be reported once the kernel has unwound.
+fn answer_input(
+  link: &mut Link,
+  request: InputRequest,
+  input: Option<&InputChannel>,
+  abort: &AbortSignal,
+  abort_sent: &mut bool,
+  input_failure: &mut Option<anyhow::Error>
+) -> anyhow::Result<()> {
+  let text = match request.ask(input, abort) {
+    | Ok(text) => text,
+    | Err(e) => {
+      if !abort.is_requested() {
+        *input_failure = Some(e);
+      }
+      if !*abort_sent {
+        link
+          .put_message(UrgentMessage::ABORT)
+          .map_err(|e| link_error("sending the abort message", e))?;
+        *abort_sent = true;
+      }
+      // `Input[]` evaluates this; for `InputString[]` the pending abort
+      // message does the job.
+      "Abort[]".to_string()
+    }
+  };
+  link
+    .put_expr(&Expr::normal(wolfram_expr::Symbol::new("System`EnterTextPacket"), vec![
+      Expr::string(&text),
+    ]))
+    .map_err(|e| link_error("sending input", e))?;
+  link.flush().map_err(|e| link_error("flush", e))
+}
+
+/// Decode a [`PROGRESS_DEFINITION`] report, or `None` for any other packet
+/// content.
+fn decode_progress(expr: &Expr) -> Option<EvalEvent> {
+  let [done, total, message] = expr_tree::marker_args(expr, "ProgressReport")? else {
+    return None;
+  };
+  let number = |expr: &Expr| {
+    match expr.kind() {
+      | ExprKind::Real(r) => Some(r.into_inner()),
+      | ExprKind::Integer(i) => Some(*i as f64),
+      | _ => None
+    }
+  };
+  let message = expr_tree::string(message).ok()?;
+  Some(EvalEvent::Progress {
+    done:    number(done)?,
+    total:   number(total),
+    message: Some(message).filter(|m| !m.is_empty())
+  })
+}
+
+/// Decode the wrapper's `MathematicaMCP`Result[...]`.
+fn decode_result(
+  expr: &Expr,
+  formats: &[OutputFormat],
+  graphics_format: GraphicsFormat,
+  logs: Vec<String>
+) -> anyhow::Result<EvalResult> {
+  let Some([outputs, graphics, messages, tree]) = expr_tree::marker_args(expr, "Result") else {
+    return Err(anyhow!("unexpected result from kernel: {expr}"));
+  };
+
+  let texts = expr_tree::marker_args(outputs, "Outputs")
+    .ok_or_else(|| anyhow!("malformed outputs from kernel: {outputs}"))?;
+  let outputs = formats
+    .iter()
+    .zip(texts)
+    .map(|(&format, text)| {
+      Ok(FormattedOutput {
+        format,
+        text: expr_tree::string(text)?
+      })
+    })
+    .collect::<anyhow::Result<Vec<_>>>()?;
+  let output = outputs.first().map(|o| o.text.clone()).unwrap_or_default();
+
+  let mut export_failures = Vec::new();
+  let mut exported = Vec::new();
+  for graphic in expr_tree::marker_args(graphics, "Graphics")
+    .ok_or_else(|| anyhow!("malformed graphics from kernel: {graphics}"))?
+  {
+    match decode_graphic(graphic, graphics_format)? {
+      | Ok(artifact) => exported.push(artifact),
+      | Err(failure) => export_failures.push(failure)
+    }
+  }
+
+  let mut messages = expr_tree::marker_args(messages, "Messages")
+    .ok_or_else(|| anyhow!("malformed messages from kernel: {messages}"))?
+    .iter()
+    .map(|message| {
+      let Some([symbol, tag, text, arguments @ ..]) = expr_tree::marker_args(message, "Message")
+      else {
+        return Err(anyhow!("malformed message from kernel: {message}"));
+      };
+      Ok(WolframMessage {
+        symbol:    expr_tree::string(symbol)?,
+        tag:       expr_tree::string(tag)?,
+        text:      expr_tree::string(text)?,
+        arguments: arguments.iter().map(expr_tree::string).collect::<anyhow::Result<_>>()?
+      })
+    })
+    .collect::<anyhow::Result<Vec<_>>>()?;
+  messages.extend(export_failures);
+
+  let expression = if expr_tree::is_marker(tree, "None") {
+    None
+  } else {
+    Some(ExprTree::decode(tree)?)
+  };
+
+  Ok(EvalResult {
+    output,
+    outputs,
+    logs,
+    graphics: exported,
+    messages,
+    aborted: false,
+    expression
+  })
+}
+
+/// Decode one graphic, or, when the kernel could not export it, a message
+/// saying so in its place.
+fn decode_graphic(
+  graphic: &Expr,
+  format: GraphicsFormat
+) -> anyhow::Result<Result<GraphicsArtifact, WolframMessage>> {
+  let malformed = || anyhow!("malformed graphic from kernel: {graphic}");
+  let Some([data, head, position]) = expr_tree::marker_args(graphic, "Graphic") else {
+    return Err(malformed());
+  };
+  let position = expr_tree::marker_args(position, "Position")
+    .ok_or_else(malformed)?
+    .iter()
+    .map(|part| {
+      match part.kind() {
+        | ExprKind::Integer(i) => Ok(Part::Index(*i)),
+        | _ => {
+          let [key] = expr_tree::marker_args(part, "Key").ok_or_else(malformed)? else {
+            return Err(malformed());
+          };
+          Ok(Part::Key {
+            key: expr_tree::string(key)?
+          })
+        }
       }
+    })
+    .collect::<anyhow::Result<Vec<_>>>()?;
+  let head = expr_tree::string(head)?;
+  if expr_tree::is_marker(data, "ExportFailed") {
+    let position = position.iter().map(Part::to_string).collect::<Vec<_>>().join(", ");
+    return Ok(Err(WolframMessage {
+      symbol:    "MathematicaMCP".to_string(),
+      tag:       "noexport".to_string(),
+      text:      format!(
+        "The {head} at position {{{position}}} could not be exported as {}; it is left out of the \
+         graphics.",
+        format.export_name()
+      ),
+      arguments: Vec::new()
+    }));
+  }
+  Ok(Ok(GraphicsArtifact {
+    position,
+    head,
+    format,
+    data: expr_tree::string(data)?
+  }))
+}
+
+/// Evaluate `Null` and wait for the answer, so that an abort the kernel has
+/// not acted on yet is spent on it. The answer, `Null` or `$Aborted`, arrives
+/// without a context and is skipped rather than read.
+fn drain_abort(link: &mut Link) -> anyhow::Result<()> {
+  tracing::debug!("draining an abort that arrived after the result");
+  link.put_function("System`EvaluatePacket", 1).map_err(|e| link_error("put_eval_packet", e))?;
+  link.put_symbol("System`Null").map_err(|e| link_error("put_eval_packet", e))?;
+  link.end_packet().map_err(|e| link_error("put_eval_packet", e))?;
+  link.flush().map_err(|e| link_error("flush", e))?;
+  loop {
+    // Already sent, so no second abort goes out while waiting.
+    let mut abort_sent = true;
+    wait_for_packet(link, &AbortSignal::default(), &mut abort_sent)?;
+    let pkt = link.raw_next_packet().map_err(|e| link_error("raw_next_packet", e))?;
+    link.new_packet().map_err(|e| link_error("new_packet", e))?;
+    if pkt == wstp::sys::RETURNPKT {
+      return Ok(());
     }
   }
 }
 
+/// Block until the kernel has a packet for us. If an abort is requested while
+/// waiting, send the WSTP abort message (once) and keep waiting: the kernel
+/// answers with `$Aborted`, which the packet loop then drains normally.
+fn wait_for_packet(
+  link: &mut Link,
+  abort: &AbortSignal,
+  abort_sent: &mut bool
+) -> anyhow::Result<()> {
+  loop {
+    let already_sent = *abort_sent;
+    let ready = link
+      .wait_with_callback(|_: &mut Link| {
+        if !already_sent && abort.is_requested() {
+          ControlFlow::Break(())
+        } else {
+          ControlFlow::Continue(())
+        }
+      })
+      .map_err(|e| link_error("waiting for kernel", e))?;
+    if ready {
+      return Ok(());
+    }
+
+    tracing::info!("aborting kernel evaluation");
+    link
+      .put_message(UrgentMessage::ABORT)
+      .map_err(|e| link_error("sending the abort message", e))?;
+    *abort_sent = true;
+  }
+}
+
 pub fn build_financial_data_code(
   symbol: &str,
   property: Option<&str>,
@@ -190,7 +1201,7 @@ fn iso_date_to_dateobject(s: &str) -> anyhow::Result<String> {
   Ok(format!("DateObject[{{{}, {}, {}}}]", d.year(), d.month(), d.day()))
 }
 
-fn wl_string(s: &str) -> String {
+pub(crate) fn wl_string(s: &str) -> String {
   // Escape for inclusion inside "..."
   s.replace('\\', "\\\\").replace('\"', "\\\"")
 }
@@ -205,3 +1216,167 @@ fn find_in_path(exe: &str) -> Option<PathBuf> {
   }
   None
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn graphics_options_build_export_arguments() {
+    assert_eq!(GraphicsOptions::default().export_args().unwrap(), "\"PNG\"");
+
+    let options = GraphicsOptions {
+      format:     Some(GraphicsFormat::Jpeg),
+      resolution: Some(300),
+      image_size: Some(ImageSize::WidthHeight([400, 300])),
+      background: Some("#1f77b4".to_string())
+    };
+    assert_eq!(
+      options.export_args().unwrap(),
+      "\"JPEG\", ImageResolution -> 300, ImageSize -> {400, 300}, Background -> \
+       RGBColor[\"#1f77b4\"]"
+    );
+
+    let named = GraphicsOptions {
+      image_size: Some(ImageSize::Width(600)),
+      background: Some("None".to_string()),
+      ..GraphicsOptions::default()
+    };
+    assert_eq!(named.export_args().unwrap(), "\"PNG\", ImageSize -> 600, Background -> None");
+
+    for bad in ["#12345", "white", "Red]; Quit[", "Quit", "Exit", "Abort", ""] {
+      let options = GraphicsOptions {
+        background: Some(bad.to_string()),
+        ..GraphicsOptions::default()
+      };
+      assert!(options.export_args().is_err(), "{bad}");
+    }
+  }
+
+  /// A loopback link holding the `ReturnPacket` a kernel would answer with,
+  /// its content written by `reply`. `evaluate` reads it back ahead of its own
+  /// request, which it writes to the same link.
+  fn kernel_replying(reply: impl FnOnce(&mut Link) -> Result<(), wstp::Error>) -> Link {
+    let mut link = Link::new_loopback().unwrap();
+    link.put_function("ReturnPacket", 1).unwrap();
+    reply(&mut link).unwrap();
+    link.end_packet().unwrap();
+    link.flush().unwrap();
+    link
+  }
+
+  #[test]
+  fn code_that_does_not_parse_is_reported_as_invalid_syntax() {
+    let mut link = kernel_replying(|link| link.put_symbol("MathematicaMCP`SyntaxError"));
+    let err =
+      evaluate(&mut link, "f[2]]", &EvalOptions::default(), &AbortSignal::default()).unwrap_err();
+    assert!(err.is::<InvalidSyntax>(), "{err}");
+
+    // The kernel is only asked to read the wrapper once the code parses.
+    assert_eq!(link.raw_next_packet().unwrap(), wstp::sys::EVALUATEPKT);
+    let request = link.get_expr().unwrap();
+    let ExprKind::Normal(guard) = request.kind() else {
+      panic!("unexpected request: {request}");
+    };
+    assert_eq!(guard.head(), &Expr::symbol(wolfram_expr::Symbol::new("System`If")));
+    assert_eq!(
+      guard.elements()[0],
+      Expr::normal(wolfram_expr::Symbol::new("System`SyntaxQ"), vec![Expr::string("f[2]]")])
+    );
+  }
+
+  #[test]
+  fn a_late_abort_is_drained_by_a_sentinel_evaluation() {
+    // The kernel spends the pending abort on the sentinel and answers with a
+    // bare `$Aborted`, which `get_expr` could not read.
+    let mut link = kernel_replying(|link| link.put_symbol("$Aborted"));
+    drain_abort(&mut link).unwrap();
+
+    assert_eq!(link.raw_next_packet().unwrap(), wstp::sys::EVALUATEPKT);
+    assert_eq!(link.get_expr().unwrap(), Expr::symbol(wolfram_expr::Symbol::new("System`Null")));
+  }
+
+  #[test]
+  fn parts_display_as_part_specifications() {
+    let key = Part::Key {
+      key: "\"plot\"".to_string()
+    };
+    assert_eq!(Part::Index(2).to_string(), "2");
+    assert_eq!(key.to_string(), "Key[\"plot\"]");
+  }
+
+  #[test]
+  fn call_options_fall_back_to_session_defaults() {
+    let session = GraphicsOptions {
+      format: Some(GraphicsFormat::Svg),
+      resolution: Some(144),
+      ..GraphicsOptions::default()
+    };
+    let call = GraphicsOptions {
+      resolution: Some(300),
+      ..GraphicsOptions::default()
+    };
+    let merged = call.or(&session);
+    assert_eq!(merged.format, Some(GraphicsFormat::Svg));
+    assert_eq!(merged.resolution, Some(300));
+  }
+
+  #[test]
+  fn graphics_that_fail_to_export_are_reported_as_messages() {
+    let marker = |name: &str, args| {
+      Expr::normal(wolfram_expr::Symbol::new(&format!("MathematicaMCP`{name}")), args)
+    };
+    let symbol =
+      |name: &str| Expr::symbol(wolfram_expr::Symbol::new(&format!("MathematicaMCP`{name}")));
+    let result = marker("Result", vec![
+      marker("Outputs", vec![Expr::string("{-Graphics-, -Image3D-}")]),
+      marker("Graphics", vec![
+        marker("Graphic", vec![
+          Expr::string("iVBOR"),
+          Expr::string("Graphics"),
+          marker("Position", vec![Expr::from(1)]),
+        ]),
+        marker("Graphic", vec![
+          symbol("ExportFailed"),
+          Expr::string("Image3D"),
+          marker("Position", vec![Expr::from(2)]),
+        ]),
+      ]),
+      marker("Messages", vec![]),
+      symbol("None"),
+    ]);
+
+    let res =
+      decode_result(&result, &[OutputFormat::InputForm], GraphicsFormat::Png, Vec::new()).unwrap();
+    assert_eq!(res.output, "{-Graphics-, -Image3D-}");
+    assert_eq!(res.graphics.len(), 1);
+    assert_eq!(res.graphics[0].head, "Graphics");
+    assert_eq!(res.messages.len(), 1);
+    assert_eq!(res.messages[0].tag, "noexport");
+    assert!(res.messages[0].text.contains("Image3D at position {2}"), "{}", res.messages[0].text);
+  }
+
+  #[test]
+  fn progress_reports_decode_into_events() {
+    let report =
+      |args| Expr::normal(wolfram_expr::Symbol::new("MathematicaMCP`ProgressReport"), args);
+    let none = Expr::symbol(wolfram_expr::Symbol::new("MathematicaMCP`None"));
+    assert_eq!(
+      decode_progress(&report(vec![Expr::real(3.0), Expr::real(10.0), Expr::string("fitting")])),
+      Some(EvalEvent::Progress {
+        done:    3.0,
+        total:   Some(10.0),
+        message: Some("fitting".to_string())
+      })
+    );
+    assert_eq!(
+      decode_progress(&report(vec![Expr::from(7), none, Expr::string("")])),
+      Some(EvalEvent::Progress {
+        done:    7.0,
+        total:   None,
+        message: None
+      })
+    );
+    assert_eq!(decode_progress(&Expr::string("3")), None);
+  }
+}